@@ -1,13 +1,50 @@
 mod slaves;
 
-use std::time::Duration;
+use std::{env, process, time::Duration};
 
+use anyhow::{anyhow, Result};
 use slaves::{daemon::FetchDaemon, saver::Saver, saver::SaverType};
 
-use crate::slaves::serializer::SerType;
+use crate::slaves::{clients::cassette::CassetteConfig, serializer::SerType};
+
+const USAGE: &str = "Usage: big_brother [--aims DIR] [--record DIR | --replay DIR]";
+
+struct Args {
+    aims: String,
+    cassette: Option<CassetteConfig>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        aims: "aims".to_string(),
+        cassette: None,
+    };
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| anyhow!("{} expects a value", arg))
+        };
+        match arg.as_str() {
+            "--aims" => args.aims = value()?,
+            "--record" => args.cassette = Some(CassetteConfig::record(&value()?)),
+            "--replay" => args.cassette = Some(CassetteConfig::replay(&value()?)),
+            _ => return Err(anyhow!("Unknown argument {}", arg)),
+        }
+    }
+    Ok(args)
+}
 
 #[tokio::main]
 async fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, USAGE);
+        process::exit(2)
+    });
+    if let Some(cassette) = args.cassette {
+        CassetteConfig::set_global(cassette).unwrap();
+    }
+
     let mut savers = vec![];
     savers.push(Saver::new_default().await);
     savers.push(Saver::new_file_json("/tmp/fetched.txt".to_string()).await);
@@ -15,7 +52,7 @@ async fn main() {
     savers.push(Saver::new(SaverType::Postgres, SerType::Json).await);
     let saver = Saver::new_saver_json(SaverType::Multiple(savers)).await;
 
-    FetchDaemon::new_default(Duration::from_secs(10), saver)
+    FetchDaemon::new(Duration::from_secs(10), args.aims, saver)
         .start()
         .await;
 }
@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::unix_now;
+
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct CacheConfig {
+    #[serde(default = "CacheConfig::default_dir")]
+    pub dir: String,
+    /// Cached pages younger than this are served without any request
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+impl CacheConfig {
+    fn default_dir() -> String {
+        "cache".to_string()
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            dir: Self::default_dir(),
+            ttl_secs: None,
+        }
+    }
+}
+
+/// Returned by `get_text` when the server says the page didn't change since the last fetch,
+/// with the body it had then.
+#[derive(Debug)]
+pub struct NotModified(pub String);
+
+impl Display for NotModified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Not modified")
+    }
+}
+
+impl std::error::Error for NotModified {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CachedResponse {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub stored_at: u64,
+    pub body: String,
+}
+
+impl CachedResponse {
+    pub fn is_fresh(&self, ttl_secs: Option<u64>) -> bool {
+        ttl_secs.is_some_and(|ttl| unix_now() < self.stored_at.saturating_add(ttl))
+    }
+}
+
+/// Last response with an `ETag` or `Last-Modified` of every url, so conditional requests are
+/// sent for all aims, whether they have a `cache` section or not.
+#[derive(Debug, Default)]
+pub struct Validators(Mutex<HashMap<String, CachedResponse>>);
+
+impl Validators {
+    pub fn global() -> &'static Validators {
+        static GLOBAL: OnceLock<Validators> = OnceLock::new();
+        GLOBAL.get_or_init(Validators::default)
+    }
+
+    pub fn load(&self, url: &str) -> Option<CachedResponse> {
+        self.0.lock().unwrap().get(url).cloned()
+    }
+
+    pub fn store(&self, entry: &CachedResponse) {
+        if entry.etag.is_some() || entry.last_modified.is_some() {
+            let mut entries = self.0.lock().unwrap();
+            entries.insert(entry.url.clone(), entry.clone());
+        }
+    }
+}
+
+/// On-disk responses keyed by url, one json file per url.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    pub fn new(dir: &str) -> Self {
+        ResponseCache {
+            dir: Path::new(dir).to_path_buf(),
+        }
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        // FNV-1a, file names have to stay the same between builds
+        let hash = url.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        self.dir.join(format!("{:016x}.json", hash))
+    }
+
+    pub fn load(&self, url: &str) -> Option<CachedResponse> {
+        let content = fs::read_to_string(self.path(url)).ok()?;
+        serde_json::from_str::<CachedResponse>(&content)
+            .ok()
+            .filter(|entry| entry.url == url)
+    }
+
+    pub fn store(&self, entry: &CachedResponse) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(&entry.url), serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::slaves::clients::unix_now;
+
+    use super::{CachedResponse, ResponseCache};
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let dir = "test/cache_roundtrip";
+        let cache = ResponseCache::new(dir);
+        let entry = CachedResponse {
+            url: "http://example.com/".to_string(),
+            etag: Some("\"abc\"".to_string()),
+            last_modified: None,
+            stored_at: unix_now(),
+            body: "<html></html>".to_string(),
+        };
+        cache.store(&entry).unwrap();
+        let loaded = cache.load("http://example.com/").unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(loaded, entry);
+        assert!(loaded.is_fresh(Some(60)));
+        assert!(!loaded.is_fresh(None));
+        assert!(!CachedResponse {
+            stored_at: 0,
+            ..loaded
+        }
+        .is_fresh(Some(60)));
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::{Method, Request, Response, ResponseBuilderExt, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::slaves::fetchers::FetcherConfig;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub enum CassetteMode {
+    /// Requests go out as usual, responses are written into the aim's cassette
+    Record,
+    /// Nothing goes out, responses are served from the aim's cassette
+    Replay,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct CassetteConfig {
+    pub mode: CassetteMode,
+    #[serde(default = "CassetteConfig::default_dir")]
+    pub dir: String,
+}
+
+static GLOBAL: OnceLock<CassetteConfig> = OnceLock::new();
+
+impl CassetteConfig {
+    fn default_dir() -> String {
+        "cassettes".to_string()
+    }
+
+    pub fn replay(dir: &str) -> Self {
+        CassetteConfig {
+            mode: CassetteMode::Replay,
+            dir: dir.to_string(),
+        }
+    }
+
+    pub fn record(dir: &str) -> Self {
+        CassetteConfig {
+            mode: CassetteMode::Record,
+            dir: dir.to_string(),
+        }
+    }
+
+    /// Mode for aims without their own `cassette` section, set once from the command line.
+    pub fn set_global(config: CassetteConfig) -> Result<()> {
+        GLOBAL
+            .set(config)
+            .map_err(|_| anyhow!("Cassette mode is already set"))
+    }
+
+    pub fn for_aim(config: &FetcherConfig) -> Option<&CassetteConfig> {
+        config.cassette.as_ref().or_else(|| GLOBAL.get())
+    }
+}
+
+/// Single request and the response it got. Text bodies are kept as is so cassettes
+/// can be read and edited by hand, anything else is stored in base64.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    /// `name: value` lines
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_base64: Option<String>,
+}
+
+impl Interaction {
+    fn matches(&self, method: &Method, url: &Url) -> bool {
+        self.method.eq_ignore_ascii_case(method.as_str())
+            && Url::parse(&self.url).is_ok_and(|recorded| &recorded == url)
+    }
+
+    fn body_bytes(&self) -> Result<Vec<u8>> {
+        match (&self.body, &self.body_base64) {
+            (_, Some(encoded)) => Ok(base64::decode(encoded)?),
+            (Some(body), None) => Ok(body.clone().into_bytes()),
+            (None, None) => Ok(vec![]),
+        }
+    }
+
+    fn to_response(&self) -> Result<Response> {
+        let mut builder = http::Response::builder()
+            .status(self.status)
+            .url(Url::parse(&self.url)?);
+        for header in self.headers.iter() {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Malformed header {}", header))?;
+            builder = builder.header(name.trim(), value.trim());
+        }
+        Ok(builder.body(self.body_bytes()?)?.into())
+    }
+}
+
+/// Responses of one aim, stored in `<dir>/<aim url>.yaml`.
+#[derive(Debug)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    interactions: Mutex<Vec<Interaction>>,
+    replayed: Mutex<Vec<bool>>,
+}
+
+impl Cassette {
+    pub fn open(config: &CassetteConfig, aim_url: &str) -> Result<Self> {
+        let path = Path::new(&config.dir).join(format!("{}.yaml", Self::file_stem(aim_url)));
+        let interactions: Vec<Interaction> = match config.mode {
+            CassetteMode::Record => vec![],
+            CassetteMode::Replay => {
+                let content = fs::read_to_string(&path)
+                    .with_context(|| format!("No cassette {:?} to replay", path))?;
+                serde_yaml::from_str(&content)?
+            }
+        };
+        Ok(Cassette {
+            mode: config.mode,
+            path,
+            replayed: Mutex::new(vec![false; interactions.len()]),
+            interactions: Mutex::new(interactions),
+        })
+    }
+
+    /// `http://example.com/` is stored in `example.com.yaml`
+    fn file_stem(aim_url: &str) -> String {
+        let without_scheme = aim_url.split_once("://").map_or(aim_url, |(_, rest)| rest);
+        without_scheme
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>()
+            .trim_matches('_')
+            .to_string()
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.mode == CassetteMode::Replay
+    }
+
+    /// Serves the first not yet replayed response recorded for the request,
+    /// repeating the last one when the aim asks for the same url again.
+    pub fn replay(&self, request: &Request) -> Result<Response> {
+        let interactions = self.interactions.lock().unwrap();
+        let mut replayed = self.replayed.lock().unwrap();
+        let matching: Vec<usize> = (0..interactions.len())
+            .filter(|&i| interactions[i].matches(request.method(), request.url()))
+            .collect();
+        let index = matching
+            .iter()
+            .find(|&&i| !replayed[i])
+            .or_else(|| matching.last())
+            .copied()
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} {} is not recorded in {:?}",
+                    request.method(),
+                    request.url(),
+                    self.path
+                )
+            })?;
+        replayed[index] = true;
+        interactions[index].to_response()
+    }
+
+    /// Stores the response and gives back an equivalent one, since reading the body consumes it.
+    pub async fn record(&self, method: &Method, url: &Url, resp: Response) -> Result<Response> {
+        let status = resp.status().as_u16();
+        let headers = resp
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some(format!("{}: {}", name, value.to_str().ok()?)))
+            .collect();
+        let bytes = resp.bytes().await?.to_vec();
+        let (body, body_base64) = match String::from_utf8(bytes) {
+            Ok(text) => (Some(text), None),
+            Err(err) => (None, Some(base64::encode(err.as_bytes()))),
+        };
+        let interaction = Interaction {
+            method: method.to_string(),
+            url: url.to_string(),
+            status,
+            headers,
+            body,
+            body_base64,
+        };
+        let resp = interaction.to_response()?;
+
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(interaction);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_yaml::to_string(&*interactions)?)?;
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use reqwest::{Client, Method, Url};
+
+    use crate::slaves::clients::http::tests::{response, serve};
+
+    use super::{Cassette, CassetteConfig};
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(Cassette::file_stem("http://example.com/"), "example.com");
+        assert_eq!(
+            Cassette::file_stem("https://market.yandex.ru/product?sku=1"),
+            "market.yandex.ru_product_sku_1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = "test/cassettes_roundtrip";
+        let (url, server) = serve(vec![response(
+            "200 OK",
+            &["content-type: text/html"],
+            "<p>recorded</p>",
+        )])
+        .await;
+        let parsed_url = Url::parse(&url).unwrap();
+
+        let recorder = Cassette::open(&CassetteConfig::record(dir), &url).unwrap();
+        let resp = Client::new().get(&url).send().await.unwrap();
+        let resp = recorder
+            .record(&Method::GET, &parsed_url, resp)
+            .await
+            .unwrap();
+        assert_eq!(resp.text().await.unwrap(), "<p>recorded</p>");
+        server.await.unwrap();
+
+        let player = Cassette::open(&CassetteConfig::replay(dir), &url).unwrap();
+        let request = Client::new().get(&url).build().unwrap();
+        let replayed = player.replay(&request).unwrap();
+        assert_eq!(replayed.status(), 200);
+        assert_eq!(replayed.headers()["content-type"], "text/html");
+        assert_eq!(replayed.text().await.unwrap(), "<p>recorded</p>");
+
+        let other = Client::new().post(&url).build().unwrap();
+        assert!(player.replay(&other).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
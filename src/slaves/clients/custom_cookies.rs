@@ -1,10 +1,20 @@
 use reqwest::{header::HeaderValue, Url};
 
-use anyhow::{anyhow, Result};
-use std::{fs::File, io::BufReader, path::Path, sync::RwLock};
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
 
 use bytes::Bytes;
 
+use crate::slaves::fetchers::CookiesConfig;
+
+use super::unix_now;
+
 pub struct Cookie<'a>(pub cookie::Cookie<'a>);
 
 impl<'a> Cookie<'a> {
@@ -17,12 +27,81 @@ impl<'a> Cookie<'a> {
     }
 }
 
+/// Single cookie record taken from a browser export.
+#[derive(Debug, PartialEq)]
+struct ImportedCookie {
+    domain: String,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    expiry: u64,
+    name: String,
+    value: String,
+}
+
+impl ImportedCookie {
+    /// Netscape `cookies.txt` line: domain, subdomains flag, path, secure, expiry, name, value.
+    /// Lines with the `#HttpOnly_` prefix are http-only cookies, other comments are skipped.
+    fn from_netscape_line(line: &str) -> Option<Self> {
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(rest) => (rest, true),
+            None if line.starts_with('#') => return None,
+            None => (line, false),
+        };
+        let fields: Vec<_> = line
+            .trim_end_matches(&['\r', '\n'][..])
+            .split('\t')
+            .collect();
+        if fields.len() != 7 {
+            return None;
+        }
+        Some(ImportedCookie {
+            domain: fields[0].to_string(),
+            path: fields[2].to_string(),
+            secure: fields[3].eq_ignore_ascii_case("TRUE"),
+            http_only,
+            expiry: fields[4].parse().ok()?,
+            name: fields[5].to_string(),
+            value: fields[6].to_string(),
+        })
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expiry != 0 && self.expiry <= now
+    }
+
+    /// Set-Cookie representation, so that the store applies its usual domain and path rules.
+    fn to_set_cookie(&self, now: u64) -> String {
+        let mut header = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if self.domain.starts_with('.') {
+            header.push_str(&format!("; Domain={}", self.domain));
+        }
+        if self.expiry != 0 {
+            header.push_str(&format!("; Max-Age={}", self.expiry.saturating_sub(now)));
+        }
+        if self.secure {
+            header.push_str("; Secure");
+        }
+        if self.http_only {
+            header.push_str("; HttpOnly");
+        }
+        header
+    }
+
+    fn origin(&self) -> Result<Url> {
+        let scheme = if self.secure { "https" } else { "http" };
+        let host = self.domain.trim_start_matches('.');
+        Url::parse(&format!("{}://{}{}", scheme, host, self.path))
+            .with_context(|| format!("Invalid cookie domain {}", self.domain))
+    }
+}
+
 #[derive(Debug)]
-struct CookiePath(String);
+struct CookiePath(PathBuf);
 
 impl Default for CookiePath {
     fn default() -> Self {
-        Self("/tmp/cookies".to_string())
+        Self(Path::new(&CookiesConfig::default().dir).join("default"))
     }
 }
 
@@ -34,6 +113,7 @@ impl reqwest::cookie::CookieStore for MyJar {
         let iter =
             cookie_headers.filter_map(|val| Cookie::parse(val).map(|c| c.0.into_owned()).ok());
 
+        // kept in memory, the client stores the jar once the request is done
         self.0.write().unwrap().store_response_cookies(iter, url);
     }
 
@@ -42,8 +122,8 @@ impl reqwest::cookie::CookieStore for MyJar {
             .0
             .read()
             .unwrap()
-            .get_request_cookies(url)
-            .map(|c| format!("{}={}", c.name(), c.value()))
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
             .collect::<Vec<_>>()
             .join("; ");
 
@@ -56,25 +136,101 @@ impl reqwest::cookie::CookieStore for MyJar {
 }
 
 impl MyJar {
-    pub fn new(cookies_file: String) -> Self {
-        let cookies_path = format!("cookies/{}", cookies_file);
-        let cookies = if Path::new(&cookies_path[..]).exists() {
-            let f = BufReader::new(File::open(&cookies_path).unwrap());
-            Self(
-                RwLock::new(cookie_store::CookieStore::load_json(f).unwrap()),
-                CookiePath(cookies_path),
-            )
+    /// Loads the saved jar, browser exports are imported only into a new one so they don't
+    /// overwrite the cookies the server has set since.
+    pub fn new(config: &CookiesConfig, cookies_file: String) -> Result<Self> {
+        let cookies_path = Path::new(&config.dir).join(cookies_file);
+        if cookies_path.exists() {
+            let f = BufReader::new(File::open(&cookies_path)?);
+            let store = cookie_store::CookieStore::load_json(f)
+                .map_err(|_| anyhow!("Couldn't load cookies from {:?}", cookies_path))?;
+            return Ok(Self(RwLock::new(store), CookiePath(cookies_path)));
+        }
+        let jar = Self(RwLock::default(), CookiePath(cookies_path));
+        for import_path in config.import.iter() {
+            jar.import(import_path)
+                .with_context(|| format!("Couldn't import cookies from {}", import_path))?;
+        }
+        Ok(jar)
+    }
+
+    /// Imports cookies from a Netscape `cookies.txt` or a Firefox `cookies.sqlite` file,
+    /// returns the number of cookies that made it into the jar.
+    pub fn import(&self, path: &str) -> Result<usize> {
+        let cookies = if path.ends_with(".sqlite") {
+            Self::read_firefox(path)?
         } else {
-            Self(
-                RwLock::new(cookie_store::CookieStore::default()),
-                CookiePath(cookies_path),
-            )
+            Self::read_netscape(path)?
         };
-        cookies
+        let now = unix_now();
+        let mut store = self.0.write().unwrap();
+        let mut imported = 0;
+        for cookie in cookies.iter().filter(|c| !c.is_expired(now)) {
+            if store
+                .parse(&cookie.to_set_cookie(now), &cookie.origin()?)
+                .is_ok()
+            {
+                imported += 1;
+            }
+        }
+        Ok(imported)
+    }
+
+    fn read_netscape(path: &str) -> Result<Vec<ImportedCookie>> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut cookies = vec![];
+        for line in reader.lines() {
+            if let Some(cookie) = ImportedCookie::from_netscape_line(&line?) {
+                cookies.push(cookie);
+            }
+        }
+        Ok(cookies)
+    }
+
+    fn read_firefox(path: &str) -> Result<Vec<ImportedCookie>> {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut stmt = conn.prepare(
+            "SELECT host, path, isSecure, isHttpOnly, expiry, name, value FROM moz_cookies",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ImportedCookie {
+                domain: row.get(0)?,
+                path: row.get(1)?,
+                secure: row.get::<_, i64>(2)? != 0,
+                http_only: row.get::<_, i64>(3)? != 0,
+                expiry: row.get::<_, i64>(4)?.max(0) as u64,
+                name: row.get(5)?,
+                value: row.get(6)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Drops expired cookies from the jar.
+    pub fn purge_expired(&self) {
+        let mut store = self.0.write().unwrap();
+        let expired: Vec<_> = store
+            .iter_any()
+            .filter(|c| c.is_expired())
+            .map(|c| {
+                (
+                    String::from(&c.domain),
+                    String::from(&c.path),
+                    c.name().to_string(),
+                )
+            })
+            .collect();
+        for (domain, path, name) in expired {
+            store.remove(&domain, &path, &name);
+        }
     }
 
     pub fn store_cookies(&self) -> Result<()> {
-        let mut buffer = File::create(&self.1.0)?;
+        self.purge_expired();
+        if let Some(dir) = self.1 .0.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut buffer = File::create(&self.1 .0)?;
         self.0
             .read()
             .unwrap()
@@ -83,3 +239,78 @@ impl MyJar {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use reqwest::{cookie::CookieStore, header::HeaderValue, Url};
+
+    use crate::slaves::fetchers::CookiesConfig;
+
+    use super::{ImportedCookie, MyJar};
+
+    #[test]
+    fn test_netscape_line() {
+        let cookie = ImportedCookie::from_netscape_line(
+            "#HttpOnly_.example.com\tTRUE\t/\tTRUE\t4102444800\tsession\tabc",
+        )
+        .unwrap();
+        assert_eq!(
+            cookie,
+            ImportedCookie {
+                domain: ".example.com".to_string(),
+                path: "/".to_string(),
+                secure: true,
+                http_only: true,
+                expiry: 4102444800,
+                name: "session".to_string(),
+                value: "abc".to_string(),
+            }
+        );
+        assert!(ImportedCookie::from_netscape_line("# Netscape HTTP Cookie File").is_none());
+    }
+
+    #[test]
+    fn test_import_and_store() {
+        let dir = "test/cookies_jar";
+        let txt = format!("{}/cookies.txt", dir);
+        // a jar left by a failed run would be loaded instead of the export
+        fs::remove_dir_all(dir).ok();
+        fs::create_dir_all(dir).unwrap();
+        fs::write(
+            &txt,
+            "# Netscape HTTP Cookie File\n\
+             .example.com\tTRUE\t/\tFALSE\t4102444800\tfresh\t1\n\
+             .example.com\tTRUE\t/\tFALSE\t1\tstale\t2\n",
+        )
+        .unwrap();
+
+        let config = CookiesConfig {
+            dir: dir.to_string(),
+            import: vec![txt],
+        };
+        let jar = MyJar::new(&config, "example.com".to_string()).unwrap();
+        let url = Url::parse("http://www.example.com/").unwrap();
+        assert_eq!(jar.cookies(&url).unwrap(), "fresh=1");
+
+        jar.store_cookies().unwrap();
+        let reloaded = MyJar::new(
+            &CookiesConfig {
+                dir: dir.to_string(),
+                import: vec![],
+            },
+            "example.com".to_string(),
+        )
+        .unwrap();
+        assert_eq!(reloaded.cookies(&url).unwrap(), "fresh=1");
+
+        // the export doesn't overwrite what the server has set since
+        let header = HeaderValue::from_static("fresh=2; Domain=example.com; Path=/; Max-Age=3600");
+        reloaded.set_cookies(&mut std::iter::once(&header), &url);
+        reloaded.store_cookies().unwrap();
+        let again = MyJar::new(&config, "example.com".to_string()).unwrap();
+        assert_eq!(again.cookies(&url).unwrap(), "fresh=2");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::{anyhow, Result};
+use encoding_rs::{Encoding, UTF_8};
+
+/// Browsers look for `<meta charset>` only in the beginning of the document
+const META_SCAN_LIMIT: usize = 4096;
+
+/// Charset from a `Content-Type` value like `text/html; charset=windows-1251`.
+fn charset_param(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches(&['"', '\''][..]))
+        } else {
+            None
+        }
+    })
+}
+
+/// Charset declared with `<meta charset="...">` or
+/// `<meta http-equiv="Content-Type" content="text/html; charset=...">`.
+fn meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    let head = &body[..body.len().min(META_SCAN_LIMIT)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    head.split("<meta").skip(1).find_map(|tag| {
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let start = tag.find("charset=")? + "charset=".len();
+        let value = tag[start..].trim_start_matches(&['"', '\''][..]);
+        let end = value
+            .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+            .unwrap_or(value.len());
+        Encoding::for_label(&value.as_bytes()[..end])
+    })
+}
+
+/// Detects the encoding of a page: explicit `override_label` first, then BOM,
+/// then the `Content-Type` header and `<meta>` tags, falling back to UTF-8.
+pub fn detect(
+    body: &[u8],
+    content_type: Option<&str>,
+    override_label: Option<&str>,
+) -> Result<&'static Encoding> {
+    if let Some(label) = override_label {
+        return Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| anyhow!("Unknown encoding {}", label));
+    }
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return Ok(encoding);
+    }
+    let from_header = content_type
+        .and_then(charset_param)
+        .and_then(|label| Encoding::for_label(label.as_bytes()));
+    Ok(from_header.or_else(|| meta_charset(body)).unwrap_or(UTF_8))
+}
+
+pub fn decode(
+    body: &[u8],
+    content_type: Option<&str>,
+    override_label: Option<&str>,
+) -> Result<String> {
+    let encoding = detect(body, content_type, override_label)?;
+    // `decode` strips a BOM and may switch to the encoding it declares
+    let (text, _, _) = encoding.decode(body);
+    Ok(text.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{KOI8_R, UTF_8, WINDOWS_1251};
+
+    use super::{decode, detect};
+
+    #[test]
+    fn test_detect() {
+        let meta =
+            b"<html><head><META http-equiv=\"Content-Type\" content=\"text/html; charset=koi8-r\">";
+        assert_eq!(detect(meta, None, None).unwrap(), KOI8_R);
+        assert_eq!(
+            detect(meta, Some("text/html; charset=\"windows-1251\""), None).unwrap(),
+            WINDOWS_1251
+        );
+        assert_eq!(
+            detect(b"<meta charset='cp1251'/>", Some("text/html"), None).unwrap(),
+            WINDOWS_1251
+        );
+        assert_eq!(
+            detect(b"\xEF\xBB\xBF<meta charset=koi8-r>", None, None).unwrap(),
+            UTF_8
+        );
+        assert_eq!(detect(meta, None, Some("utf-8")).unwrap(), UTF_8);
+        assert!(detect(meta, None, Some("no-such-encoding")).is_err());
+        assert_eq!(detect(b"<html></html>", None, None).unwrap(), UTF_8);
+    }
+
+    #[test]
+    fn test_decode_windows_1251() {
+        let (body, _, _) = WINDOWS_1251.encode("<meta charset=\"windows-1251\"><p>Привет</p>");
+        assert_eq!(
+            decode(&body, Some("text/html"), None).unwrap(),
+            "<meta charset=\"windows-1251\"><p>Привет</p>"
+        );
+    }
+}
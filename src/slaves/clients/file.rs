@@ -0,0 +1,196 @@
+use std::{
+    any::Any,
+    fmt::Display,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use scraper::Html;
+
+use crate::slaves::fetchers::{FetchResults, Fetchable, FetcherConfig, HtmlFetchable};
+
+use super::encoding;
+
+/// Url reading the page from the standard input
+pub const STDIN_URL: &str = "-";
+const FILE_SCHEME: &str = "file://";
+
+/// Reads saved pages from disk instead of downloading them.
+/// `url` is `file://<path>` where the path may be a glob pattern, or `-` for stdin.
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq)]
+pub struct FileFetcher {
+    pub config: FetcherConfig,
+}
+
+impl FileFetcher {
+    pub fn handles(url: &str) -> bool {
+        url == STDIN_URL || url.starts_with(FILE_SCHEME)
+    }
+
+    fn pattern(&self) -> Result<&str> {
+        self.config
+            .url
+            .strip_prefix(FILE_SCHEME)
+            .ok_or_else(|| anyhow!("{} is not a file:// url", self.config.url))
+    }
+
+    /// Files the url points to, sorted. A plain path gives itself even if it doesn't exist,
+    /// so that reading it reports a proper error.
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        let pattern = self.pattern()?;
+        if !pattern.contains(&['*', '?', '['][..]) {
+            return Ok(vec![PathBuf::from(pattern)]);
+        }
+        let mut paths = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn stdin() -> Result<&'static [u8]> {
+        // stdin can be read only once, every daemon iteration gets the same page
+        static STDIN: OnceLock<std::result::Result<Vec<u8>, String>> = OnceLock::new();
+        STDIN
+            .get_or_init(|| {
+                let mut content = vec![];
+                std::io::stdin()
+                    .read_to_end(&mut content)
+                    .map(|_| content)
+                    .map_err(|err| err.to_string())
+            })
+            .as_deref()
+            .map_err(|err| anyhow!("Couldn't read stdin: {}", err))
+    }
+
+    fn parse(&self, content: &[u8]) -> Result<Html> {
+        let text = encoding::decode(content, None, self.config.encoding.as_deref())?;
+        Ok(Html::parse_document(&text))
+    }
+
+    fn read(&self, path: &Path) -> Result<Html> {
+        let content = std::fs::read(path).with_context(|| format!("Couldn't read {:?}", path))?;
+        self.parse(&content)
+    }
+}
+
+#[async_trait]
+impl Fetchable for FileFetcher {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn config(&self) -> &FetcherConfig {
+        &self.config
+    }
+
+    async fn fetch(&self) -> Result<FetchResults> {
+        self.fetch_page().await
+    }
+
+    async fn fetch_all(&self) -> Result<Vec<FetchResults>> {
+        if self.config.url == STDIN_URL {
+            return Ok(vec![self.fetch().await?]);
+        }
+        let mut fetched = vec![];
+        for path in self.paths()? {
+            fetched.push(self.extract(&self.read(&path)?));
+        }
+        Ok(fetched)
+    }
+}
+
+#[async_trait]
+impl HtmlFetchable for FileFetcher {
+    async fn retrieve(&self) -> Result<Html> {
+        if self.config.url == STDIN_URL {
+            return self.parse(Self::stdin()?);
+        }
+        let paths = self.paths()?;
+        match paths.as_slice() {
+            [path] => self.read(path),
+            _ => Err(anyhow!(
+                "{} matches {} files, fetch_all gives a result set per file",
+                self.config.url,
+                paths.len()
+            )),
+        }
+    }
+}
+
+impl Display for FileFetcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "FileFetcher: id={} url={}",
+            self.config.id, self.config.url
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::slaves::fetchers::{
+        ClientType, FetchItem, FetchItemType, Fetchable, FetcherConfig, FoundItemContent,
+    };
+
+    use super::FileFetcher;
+
+    fn gen_fetcher(url: &str) -> FileFetcher {
+        let title = FetchItem {
+            name: "title".to_string(),
+            path: vec!["h1".to_string()],
+            primary: true,
+            item_type: FetchItemType::Text,
+            related: vec![],
+            ..Default::default()
+        };
+        FileFetcher {
+            config: FetcherConfig {
+                client_type: ClientType::File,
+                items: vec![title],
+                url: url.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_fetcher() {
+        let dir = "test/file_fetcher";
+        fs::create_dir_all(dir).unwrap();
+        fs::write(format!("{}/a.html", dir), "<h1>First</h1>").unwrap();
+        fs::write(format!("{}/b.html", dir), "<h1>Second</h1>").unwrap();
+        fs::write(format!("{}/notes.txt", dir), "<h1>Skipped</h1>").unwrap();
+
+        let single = gen_fetcher(&format!("file://{}/a.html", dir)).fetch().await;
+        let all = gen_fetcher(&format!("file://{}/*.html", dir))
+            .fetch_all()
+            .await;
+        let missing = gen_fetcher(&format!("file://{}/missing.html", dir))
+            .fetch()
+            .await;
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            single.unwrap()[0].as_ref().unwrap().content,
+            FoundItemContent::Str("First".to_string())
+        );
+        let titles: Vec<_> = all
+            .unwrap()
+            .into_iter()
+            .map(|results| results[0].as_ref().unwrap().content.clone())
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                FoundItemContent::Str("First".to_string()),
+                FoundItemContent::Str("Second".to_string())
+            ]
+        );
+        assert!(missing.is_err());
+    }
+}
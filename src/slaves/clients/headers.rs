@@ -0,0 +1,191 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use anyhow::{anyhow, Result};
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+
+/// Set of headers a real browser sends together. Mixing headers of different browsers
+/// (e.g. a Firefox user agent with `sec-ch-ua`) is an easy way to get flagged as a bot.
+#[derive(Debug, PartialEq)]
+pub struct HeaderProfile {
+    pub name: &'static str,
+    headers: &'static [(&'static str, &'static str)],
+}
+
+const HTML_ACCEPT: &str =
+    "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
+
+pub const PROFILES: &[HeaderProfile] = &[
+    HeaderProfile {
+        name: "chrome_windows",
+        headers: &[
+            ("user-agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/92.0.4515.131 Safari/537.36"),
+            ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.9"),
+            ("accept-language", "ru-RU,ru;q=0.9,en-US;q=0.8,en;q=0.7"),
+            ("sec-ch-ua", "\"Chromium\";v=\"92\", \" Not A;Brand\";v=\"99\", \"Google Chrome\";v=\"92\""),
+            ("sec-ch-ua-mobile", "?0"),
+            ("sec-fetch-dest", "document"),
+            ("sec-fetch-mode", "navigate"),
+            ("sec-fetch-site", "none"),
+            ("sec-fetch-user", "?1"),
+            ("upgrade-insecure-requests", "1"),
+        ],
+    },
+    HeaderProfile {
+        name: "chrome_android",
+        headers: &[
+            ("user-agent", "Mozilla/5.0 (Linux; Android 11; Pixel 5) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/92.0.4515.131 Mobile Safari/537.36"),
+            ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.9"),
+            ("accept-language", "ru-RU,ru;q=0.9,en-US;q=0.8,en;q=0.7"),
+            ("sec-ch-ua", "\"Chromium\";v=\"92\", \" Not A;Brand\";v=\"99\", \"Google Chrome\";v=\"92\""),
+            ("sec-ch-ua-mobile", "?1"),
+            ("sec-fetch-dest", "document"),
+            ("sec-fetch-mode", "navigate"),
+            ("sec-fetch-site", "none"),
+            ("sec-fetch-user", "?1"),
+            ("upgrade-insecure-requests", "1"),
+        ],
+    },
+    HeaderProfile {
+        name: "firefox_linux",
+        headers: &[
+            ("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:91.0) Gecko/20100101 Firefox/91.0"),
+            ("accept", HTML_ACCEPT),
+            ("accept-language", "ru-RU,ru;q=0.8,en-US;q=0.5,en;q=0.3"),
+            ("sec-fetch-dest", "document"),
+            ("sec-fetch-mode", "navigate"),
+            ("sec-fetch-site", "none"),
+            ("sec-fetch-user", "?1"),
+            ("upgrade-insecure-requests", "1"),
+        ],
+    },
+    HeaderProfile {
+        name: "safari_mac",
+        headers: &[
+            ("user-agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/14.1.2 Safari/605.1.15"),
+            ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            ("accept-language", "ru-RU,ru;q=0.9"),
+        ],
+    },
+    HeaderProfile {
+        name: "yandex_browser",
+        headers: &[
+            ("user-agent", "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/85.0.4183.102 YaBrowser/20.9.3.189 (beta) Yowser/2.5 Safari/537.36"),
+            ("accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8"),
+            ("accept-language", "ru,en;q=0.9,zh;q=0.8,nl;q=0.7,es;q=0.6"),
+            ("accept-encoding", "identity"),
+            ("connection", "keep-alive"),
+        ],
+    },
+];
+
+impl HeaderProfile {
+    pub fn by_name(name: &str) -> Result<&'static HeaderProfile> {
+        PROFILES
+            .iter()
+            .find(|profile| profile.name == name)
+            .ok_or_else(|| anyhow!("Unknown header profile {}", name))
+    }
+
+    pub fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers {
+            headers.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Default)]
+pub enum Rotation {
+    /// Every request goes out with a randomly chosen profile
+    PerRequest,
+    /// The aim always uses the same profile, so cookies and fingerprint stay consistent
+    #[default]
+    PerSession,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Default)]
+pub struct HeadersConfig {
+    /// Profile names to choose from, all known profiles if empty
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    #[serde(default)]
+    pub rotation: Rotation,
+}
+
+impl HeadersConfig {
+    #[cfg(test)]
+    pub fn pinned(profile: &str) -> Self {
+        HeadersConfig {
+            profiles: vec![profile.to_string()],
+            rotation: Rotation::PerSession,
+        }
+    }
+
+    /// Profile for the next request of the aim with `aim_url`.
+    pub fn choose(&self, aim_url: &str) -> Result<&'static HeaderProfile> {
+        let candidates = if self.profiles.is_empty() {
+            PROFILES.iter().collect()
+        } else {
+            self.profiles
+                .iter()
+                .map(|name| HeaderProfile::by_name(name))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let index = match self.rotation {
+            Rotation::PerRequest => rand::thread_rng().gen_range(0..candidates.len()),
+            // fetchers are recreated on every daemon iteration, so the session profile is
+            // derived from the aim itself instead of being stored
+            Rotation::PerSession => {
+                let mut hasher = DefaultHasher::new();
+                aim_url.hash(&mut hasher);
+                hasher.finish() as usize % candidates.len()
+            }
+        };
+        Ok(candidates[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::USER_AGENT;
+
+    use super::{HeaderProfile, HeadersConfig, Rotation, PROFILES};
+
+    #[test]
+    fn test_profiles_are_valid() {
+        for profile in PROFILES {
+            let headers = profile.header_map();
+            assert!(headers.contains_key(USER_AGENT), "{}", profile.name);
+        }
+    }
+
+    #[test]
+    fn test_choose() {
+        let config = HeadersConfig {
+            profiles: vec!["firefox_linux".to_string(), "safari_mac".to_string()],
+            rotation: Rotation::PerSession,
+        };
+        let first = config.choose("http://example.com").unwrap();
+        for _ in 0..10 {
+            assert_eq!(config.choose("http://example.com").unwrap(), first);
+        }
+
+        let pinned = HeadersConfig::pinned("chrome_windows");
+        assert_eq!(
+            pinned.choose("http://example.com").unwrap(),
+            HeaderProfile::by_name("chrome_windows").unwrap()
+        );
+
+        let unknown = HeadersConfig::pinned("netscape_navigator");
+        assert!(unknown.choose("http://example.com").is_err());
+    }
+}
@@ -0,0 +1,481 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use reqwest::{
+    header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER},
+    Client, ClientBuilder, Request, RequestBuilder, Response, StatusCode, Url,
+};
+use serde::Deserialize;
+
+use crate::slaves::fetchers::FetcherConfig;
+
+use super::{
+    cache::{CacheConfig, CachedResponse, NotModified, ResponseCache, Validators},
+    cassette::{Cassette, CassetteConfig},
+    encoding,
+    headers::HeaderProfile,
+    limits::RateLimiter,
+    proxy::ProxyLease,
+    unix_now,
+};
+
+/// How requests of an aim are retried. Timeouts, connection errors, 429 and 5xx responses
+/// are retried, any other non-2xx status fails the fetch unless listed in `allowed_statuses`.
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct RetryConfig {
+    #[serde(default = "RetryConfig::default_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay before the second attempt, doubled for every next one
+    #[serde(default = "RetryConfig::default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// Longest delay between attempts. A `Retry-After` asking for more stops retrying.
+    #[serde(default = "RetryConfig::default_max_delay_ms")]
+    pub max_delay_ms: u64,
+    #[serde(default)]
+    pub allowed_statuses: Vec<u16>,
+}
+
+impl RetryConfig {
+    fn default_max_attempts() -> u32 {
+        3
+    }
+
+    fn default_base_delay_ms() -> u64 {
+        500
+    }
+
+    fn default_max_delay_ms() -> u64 {
+        30_000
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(
+            self.base_delay_ms
+                .saturating_mul(factor)
+                .min(self.max_delay_ms),
+        )
+    }
+
+    fn is_retryable(status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    }
+
+    fn is_allowed(&self, status: StatusCode) -> bool {
+        status.is_success()
+            || status == StatusCode::NOT_MODIFIED
+            || self.allowed_statuses.contains(&status.as_u16())
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: Self::default_max_attempts(),
+            base_delay_ms: Self::default_base_delay_ms(),
+            max_delay_ms: Self::default_max_delay_ms(),
+            allowed_statuses: vec![],
+        }
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an http date.
+fn retry_after(resp: &Response) -> Option<Duration> {
+    let value = resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Http client of a single `retrieve` call. Every request of a `Fetchable` goes through it,
+/// so header profiles, proxies, rate limits, retries and cassettes apply to all client types alike.
+#[derive(Debug)]
+pub struct AimClient {
+    pub client: Client,
+    lease: Option<ProxyLease>,
+    retry: RetryConfig,
+    cache: Option<CacheConfig>,
+    encoding: Option<String>,
+    cassette: Option<Cassette>,
+}
+
+impl AimClient {
+    /// `default_profile` is used when the aim has no `headers` section.
+    pub fn new(
+        config: &FetcherConfig,
+        mut builder: ClientBuilder,
+        default_profile: Option<&str>,
+    ) -> Result<Self> {
+        let profile = match (&config.headers, default_profile) {
+            (Some(headers), _) => Some(headers.choose(&config.url)?),
+            (None, Some(name)) => Some(HeaderProfile::by_name(name)?),
+            (None, None) => None,
+        };
+        if let Some(profile) = profile {
+            builder = builder.default_headers(profile.header_map());
+        }
+        let cassette = CassetteConfig::for_aim(config)
+            .map(|cassette| Cassette::open(cassette, &config.url))
+            .transpose()?;
+        let lease = match &cassette {
+            Some(cassette) if cassette.is_replay() => None,
+            _ => ProxyLease::for_aim(config)?,
+        };
+        let client = ProxyLease::apply(lease.as_ref(), builder)?.build()?;
+        Ok(AimClient {
+            client,
+            lease,
+            retry: config.retry.clone(),
+            cache: config.cache.clone(),
+            encoding: config.encoding.clone(),
+            cassette,
+        })
+    }
+
+    pub fn lease(&self) -> Option<&ProxyLease> {
+        self.lease.as_ref()
+    }
+
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let request = request.build()?;
+        match &self.cassette {
+            Some(cassette) if cassette.is_replay() => self.check_status(cassette.replay(&request)?),
+            Some(cassette) => {
+                let (method, url) = (request.method().clone(), request.url().clone());
+                let resp = self.send_live(request).await?;
+                cassette.record(&method, &url, resp).await
+            }
+            None => self.send_live(request).await,
+        }
+    }
+
+    async fn send_live(&self, mut request: Request) -> Result<Response> {
+        let mut attempt = 1;
+        loop {
+            let next_request = if attempt < self.retry.max_attempts {
+                request.try_clone()
+            } else {
+                None
+            };
+            let url = request.url().clone();
+            let outcome = self.execute(request).await;
+            let delay = match &outcome {
+                Ok(resp) if RetryConfig::is_retryable(resp.status()) => {
+                    Some(retry_after(resp).unwrap_or_else(|| self.retry.backoff(attempt)))
+                }
+                Err(err) if err.is_timeout() || err.is_connect() => {
+                    Some(self.retry.backoff(attempt))
+                }
+                _ => None,
+            };
+            let max_delay = Duration::from_millis(self.retry.max_delay_ms);
+            match (delay, next_request) {
+                (Some(delay), Some(next_request)) if delay <= max_delay => {
+                    eprintln!(
+                        "Attempt {} for {} failed, retrying in {:?}",
+                        attempt, url, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    request = next_request;
+                    attempt += 1;
+                }
+                _ => return self.check_status(outcome?),
+            }
+        }
+    }
+
+    pub async fn get(&self, url: &str) -> Result<Response> {
+        self.send(self.client.get(url)).await
+    }
+
+    /// Body of the page at `url`, going through the aim's response cache if it has one.
+    /// The validators of the previous response are sent along, it fails with `NotModified`
+    /// when the server confirms the page is still the same.
+    pub async fn get_text(&self, url: &str) -> Result<String> {
+        let cache = self
+            .cache
+            .as_ref()
+            .map(|config| ResponseCache::new(&config.dir));
+        let cached = Validators::global()
+            .load(url)
+            .or_else(|| cache.as_ref()?.load(url));
+        let mut request = self.client.get(url);
+        if let Some(cached) = &cached {
+            if cached.is_fresh(self.cache.as_ref().and_then(|config| config.ttl_secs)) {
+                return Ok(cached.body.clone());
+            }
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let resp = self.send(request).await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            let cached =
+                cached.ok_or_else(|| anyhow!("{} responded with 304 to a plain request", url))?;
+            // restart the ttl, the copy was just confirmed
+            let cached = CachedResponse {
+                stored_at: unix_now(),
+                ..cached
+            };
+            self.store(cache.as_ref(), &cached)?;
+            return Err(NotModified(cached.body).into());
+        }
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let body = self.read_text(resp).await?;
+        let entry = CachedResponse {
+            url: url.to_string(),
+            etag,
+            last_modified,
+            stored_at: unix_now(),
+            body,
+        };
+        self.store(cache.as_ref(), &entry)?;
+        Ok(entry.body)
+    }
+
+    fn store(&self, cache: Option<&ResponseCache>, entry: &CachedResponse) -> Result<()> {
+        Validators::global().store(entry);
+        match cache {
+            Some(cache) => cache.store(entry),
+            None => Ok(()),
+        }
+    }
+
+    /// Decodes the body using the aim's encoding override or the charset the page declares.
+    pub async fn read_text(&self, resp: Response) -> Result<String> {
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = resp.bytes().await?;
+        encoding::decode(&body, content_type.as_deref(), self.encoding.as_deref())
+    }
+
+    async fn execute(&self, request: Request) -> reqwest::Result<Response> {
+        RateLimiter::global()
+            .acquire(Self::host(request.url()))
+            .await;
+        let resp = self.client.execute(request).await;
+        if let Some(lease) = &self.lease {
+            match resp {
+                Ok(_) => lease.success(),
+                Err(_) => lease.failure(),
+            }
+        }
+        resp
+    }
+
+    fn check_status(&self, resp: Response) -> Result<Response> {
+        if self.retry.is_allowed(resp.status()) {
+            Ok(resp)
+        } else {
+            Err(anyhow!("{} responded with {}", resp.url(), resp.status()))
+        }
+    }
+
+    fn host(url: &Url) -> &str {
+        url.host_str().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::time::Duration;
+
+    use reqwest::Client;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use crate::slaves::fetchers::FetcherConfig;
+
+    use crate::slaves::clients::cache::{CacheConfig, NotModified};
+
+    use super::{AimClient, RetryConfig};
+
+    /// Serves canned raw http responses, one per connection, and returns the local url.
+    pub async fn serve<T>(responses: Vec<T>) -> (String, JoinHandle<()>)
+    where
+        T: AsRef<[u8]> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let _ = socket.read(&mut buf).await.unwrap();
+                socket.write_all(response.as_ref()).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+        });
+        (url, handle)
+    }
+
+    pub fn response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut response = format!("HTTP/1.1 {}\r\nconnection: close\r\n", status);
+        for header in headers {
+            response.push_str(header);
+            response.push_str("\r\n");
+        }
+        response + &format!("content-length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn gen_config(url: &str, retry: RetryConfig) -> FetcherConfig {
+        FetcherConfig {
+            url: url.to_string(),
+            retry,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let retry = RetryConfig {
+            base_delay_ms: 100,
+            max_delay_ms: 300,
+            ..Default::default()
+        };
+        assert_eq!(retry.backoff(1), Duration::from_millis(100));
+        assert_eq!(retry.backoff(2), Duration::from_millis(200));
+        assert_eq!(retry.backoff(3), Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_server_error() {
+        let (url, server) = serve(vec![
+            response("503 Service Unavailable", &["retry-after: 0"], "busy"),
+            response("429 Too Many Requests", &[], "slow down"),
+            response("200 OK", &[], "content"),
+        ])
+        .await;
+        let retry = RetryConfig {
+            base_delay_ms: 10,
+            ..Default::default()
+        };
+        let client = AimClient::new(&gen_config(&url, retry), Client::builder(), None).unwrap();
+        let text = client.get(&url).await.unwrap().text().await.unwrap();
+        assert_eq!(text, "content");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_status_errors() {
+        let (url, server) = serve(vec![
+            response("404 Not Found", &[], "missing"),
+            response("404 Not Found", &[], "missing"),
+        ])
+        .await;
+        let client = AimClient::new(
+            &gen_config(&url, Default::default()),
+            Client::builder(),
+            None,
+        )
+        .unwrap();
+        assert!(client.get(&url).await.is_err());
+
+        let retry = RetryConfig {
+            allowed_statuses: vec![404],
+            ..Default::default()
+        };
+        let client = AimClient::new(&gen_config(&url, retry), Client::builder(), None).unwrap();
+        assert_eq!(client.get(&url).await.unwrap().status(), 404);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_conditional_requests() {
+        let dir = "test/cache_conditional";
+        let (url, server) = serve(vec![
+            response("200 OK", &["etag: \"v1\""], "first"),
+            response("304 Not Modified", &[], ""),
+        ])
+        .await;
+        let config = FetcherConfig {
+            cache: Some(CacheConfig {
+                dir: dir.to_string(),
+                ..Default::default()
+            }),
+            ..gen_config(&url, Default::default())
+        };
+        let client = AimClient::new(&config, Client::builder(), None).unwrap();
+        assert_eq!(client.get_text(&url).await.unwrap(), "first");
+        let unchanged = client.get_text(&url).await.unwrap_err();
+        server.await.unwrap();
+
+        let fresh = FetcherConfig {
+            cache: Some(CacheConfig {
+                dir: dir.to_string(),
+                ttl_secs: Some(60),
+            }),
+            ..config
+        };
+        let client = AimClient::new(&fresh, Client::builder(), None).unwrap();
+        let cached = client.get_text(&url).await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert!(unchanged.is::<NotModified>());
+        assert_eq!(cached, "first");
+    }
+
+    #[tokio::test]
+    async fn test_conditional_requests_without_cache() {
+        let (url, server) = serve(vec![
+            response(
+                "200 OK",
+                &["last-modified: Mon, 19 Oct 2026 10:00:00 GMT"],
+                "first",
+            ),
+            response("304 Not Modified", &[], ""),
+        ])
+        .await;
+        let client = AimClient::new(
+            &gen_config(&url, Default::default()),
+            Client::builder(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(client.get_text(&url).await.unwrap(), "first");
+        let unchanged = client.get_text(&url).await.unwrap_err();
+        server.await.unwrap();
+
+        assert!(unchanged.is::<NotModified>());
+    }
+
+    #[tokio::test]
+    async fn test_read_text_decodes_meta_charset() {
+        let page = "<html><head><meta charset=\"koi8-r\"></head><body>Цена</body></html>";
+        let (body, _, _) = encoding_rs::KOI8_R.encode(page);
+        let raw = format!(
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: text/html\r\ncontent-length: {}\r\n\r\n",
+            body.len()
+        );
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(&body);
+        let (url, server) = serve(vec![raw]).await;
+        let client = AimClient::new(
+            &gen_config(&url, Default::default()),
+            Client::builder(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(client.get_text(&url).await.unwrap(), page);
+        server.await.unwrap();
+    }
+}
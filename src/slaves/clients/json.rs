@@ -0,0 +1,174 @@
+use std::{any::Any, fmt::Display};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::Value;
+
+use crate::slaves::fetchers::{
+    FetchItem, FetchItemType, FetchResults, Fetchable, FetcherConfig, FoundItem, FoundItemContent,
+};
+
+use super::{cache::NotModified, http::AimClient};
+
+/// Fetches JSON endpoints. Item paths are JSONPath expressions like `$.offers[0].price`,
+/// a `Text` item gives the first match and a `Class` item every match.
+#[derive(Debug, Clone, PartialOrd, PartialEq, Ord, Eq)]
+pub struct JsonFetcher {
+    pub config: FetcherConfig,
+}
+
+impl JsonFetcher {
+    pub async fn retrieve_json(&self) -> Result<Value> {
+        let client = AimClient::new(&self.config, Client::builder(), None)?;
+        let resp_text = client.get_text(&self.config.url).await?;
+        Ok(serde_json::from_str(&resp_text)?)
+    }
+
+    /// Strings are taken as is, anything else is kept as JSON.
+    pub fn to_text(value: &Value) -> String {
+        match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    fn process_json_item(item: &FetchItem, document: &Value) -> Option<FoundItem> {
+        let (selector, content) = item
+            .first_match(|path| {
+                let found = jsonpath_lib::select(document, path).map_err(|err| {
+                    eprintln!("Bad JSONPath {} in {}: {}", path, item.name, err);
+                    anyhow!("{}", err)
+                })?;
+                match item.item_type {
+                    _ if found.is_empty() => Err(anyhow!("Select failed")),
+                    FetchItemType::Class => Ok(FoundItemContent::Arr(
+                        found.into_iter().map(Self::to_text).collect(),
+                    )),
+                    _ => Ok(FoundItemContent::Str(Self::to_text(found[0]))),
+                }
+            })
+            .ok()?;
+        Some(FoundItem {
+            fetch_item: item.clone(),
+            content,
+            related: vec![],
+            selector,
+        })
+    }
+
+    pub fn extract_json(&self, document: &Value) -> FetchResults {
+        self.config
+            .items
+            .iter()
+            .filter(|item| item.primary)
+            .map(|primary_item| {
+                let mut found_item = Self::process_json_item(primary_item, document)?;
+                found_item.related = primary_item
+                    .related
+                    .iter()
+                    .map(|item| Self::process_json_item(item, document))
+                    .collect();
+                Some(found_item)
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Fetchable for JsonFetcher {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn config(&self) -> &FetcherConfig {
+        &self.config
+    }
+
+    async fn fetch(&self) -> Result<FetchResults> {
+        let document = match self.retrieve_json().await {
+            Err(err) if err.is::<NotModified>() => return Ok(vec![]),
+            document => document?,
+        };
+        Ok(self.extract_json(&document))
+    }
+}
+
+impl Display for JsonFetcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "JsonFetcher: id={} url={}",
+            self.config.id, self.config.url
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slaves::{
+        clients::http::tests::{response, serve},
+        fetchers::{
+            ClientType, FetchItem, FetchItemType, Fetchable, FetcherConfig, FoundItemContent,
+        },
+    };
+
+    use super::JsonFetcher;
+
+    fn gen_item(
+        name: &str,
+        path: &str,
+        item_type: FetchItemType,
+        related: Vec<FetchItem>,
+    ) -> FetchItem {
+        FetchItem {
+            name: name.to_string(),
+            path: vec![path.to_string()],
+            primary: true,
+            item_type,
+            related,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_fetcher() {
+        let (url, server) = serve(vec![response(
+            "200 OK",
+            &["content-type: application/json"],
+            r#"{"name": "Kettle", "offers": [{"shop": "A", "price": 990}, {"shop": "B", "price": 1050}]}"#,
+        )])
+        .await;
+        let fetcher = JsonFetcher {
+            config: FetcherConfig {
+                client_type: ClientType::Json,
+                items: vec![
+                    gen_item(
+                        "price",
+                        "$.offers[0].price",
+                        FetchItemType::Text,
+                        vec![gen_item("name", "$.name", FetchItemType::Text, vec![])],
+                    ),
+                    gen_item("shops", "$.offers[*].shop", FetchItemType::Class, vec![]),
+                    gen_item("missing", "$.discount", FetchItemType::Text, vec![]),
+                ],
+                url,
+                ..Default::default()
+            },
+        };
+        let fetched = fetcher.fetch().await.unwrap();
+        server.await.unwrap();
+
+        let price = fetched[0].as_ref().unwrap();
+        assert_eq!(price.content, FoundItemContent::Str("990".to_string()));
+        assert_eq!(
+            price.related[0].as_ref().unwrap().content,
+            FoundItemContent::Str("Kettle".to_string())
+        );
+        assert_eq!(
+            fetched[1].as_ref().unwrap().content,
+            FoundItemContent::Arr(vec!["A".to_string(), "B".to_string()])
+        );
+        assert!(fetched[2].is_none());
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+use crate::slaves::config_parser::parse_global_config;
+
+const GLOBAL_CONFIG_PATH: &str = "config/limits.yaml";
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Requests per second
+    pub rate: f64,
+    /// Requests allowed to go out at once after a quiet period
+    #[serde(default = "RateLimit::default_burst")]
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn default_burst() -> u32 {
+        1
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            rate: 1.0,
+            burst: Self::default_burst(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct LimitsConfig {
+    /// Aims fetched simultaneously
+    #[serde(default = "LimitsConfig::default_max_concurrent")]
+    pub max_concurrent: usize,
+    /// Limit for hosts missing in `hosts`
+    #[serde(default)]
+    pub default: RateLimit,
+    #[serde(default)]
+    pub hosts: HashMap<String, RateLimit>,
+}
+
+impl LimitsConfig {
+    fn default_max_concurrent() -> usize {
+        8
+    }
+
+    /// Limits from `config/limits.yaml` or the defaults if there is no such file.
+    pub fn global() -> &'static LimitsConfig {
+        static GLOBAL: OnceLock<LimitsConfig> = OnceLock::new();
+        GLOBAL.get_or_init(|| parse_global_config(GLOBAL_CONFIG_PATH).unwrap_or_default())
+    }
+
+    pub fn for_host(&self, host: &str) -> RateLimit {
+        self.hosts.get(host).copied().unwrap_or(self.default)
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_concurrent: Self::default_max_concurrent(),
+            default: RateLimit::default(),
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes a token if there is one, otherwise tells how long to wait for it.
+    fn try_take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst as f64);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / self.limit.rate,
+            ))
+        }
+    }
+}
+
+/// Per host token buckets shared by every request of the process.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: LimitsConfig,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn global() -> &'static RateLimiter {
+        static GLOBAL: OnceLock<RateLimiter> = OnceLock::new();
+        GLOBAL.get_or_init(|| RateLimiter::new(LimitsConfig::global().clone()))
+    }
+
+    /// Waits until a request to `host` is allowed to go out.
+    pub async fn acquire(&self, host: &str) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let bucket = buckets
+                    .entry(host.to_string())
+                    .or_insert_with(|| TokenBucket::new(self.config.for_host(host)));
+                match bucket.try_take(Instant::now()) {
+                    Ok(()) => return,
+                    Err(wait) => wait,
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Caps the number of aims fetched at the same time.
+pub fn concurrency_limit() -> Arc<Semaphore> {
+    static GLOBAL: OnceLock<Arc<Semaphore>> = OnceLock::new();
+    GLOBAL
+        .get_or_init(|| Arc::new(Semaphore::new(LimitsConfig::global().max_concurrent.max(1))))
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{LimitsConfig, RateLimit, RateLimiter, TokenBucket};
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(RateLimit {
+            rate: 2.0,
+            burst: 2,
+        });
+        assert!(bucket.try_take(start).is_ok());
+        assert!(bucket.try_take(start).is_ok());
+        assert_eq!(bucket.try_take(start), Err(Duration::from_millis(500)));
+        assert!(bucket.try_take(start + Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn test_limits_config() {
+        let config: LimitsConfig =
+            serde_yaml::from_str("max_concurrent: 2\nhosts:\n  market.yandex.ru:\n    rate: 0.2\n")
+                .unwrap();
+        assert_eq!(config.max_concurrent, 2);
+        assert_eq!(
+            config.for_host("market.yandex.ru"),
+            RateLimit {
+                rate: 0.2,
+                burst: 1
+            }
+        );
+        assert_eq!(config.for_host("example.com"), RateLimit::default());
+        // a limit without `burst` is the built-in one
+        assert_eq!(
+            serde_yaml::from_str::<RateLimit>("rate: 1.0").unwrap(),
+            RateLimit::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_waits() {
+        let limiter = RateLimiter::new(LimitsConfig {
+            default: RateLimit {
+                rate: 10.0,
+                burst: 1,
+            },
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("example.com").await;
+        }
+        limiter.acquire("another-example.com").await;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -1,2 +1,20 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod cache;
+pub mod cassette;
 mod custom_cookies;
-pub mod yandex;
\ No newline at end of file
+pub mod encoding;
+pub mod file;
+pub mod headers;
+pub mod http;
+pub mod json;
+pub mod limits;
+pub mod proxy;
+pub mod yandex;
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
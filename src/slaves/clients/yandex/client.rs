@@ -2,66 +2,49 @@ use std::{io, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use reqwest::{header::*, Client, Response, Url};
+use reqwest::{Client, Url};
 use scraper::{Html, Selector};
 
 use crate::slaves::{
-    clients::custom_cookies::MyJar,
-    fetchers::{Fetchable, FetcherConfig},
+    clients::{custom_cookies::MyJar, http::AimClient},
+    fetchers::{FetchResults, Fetchable, FetcherConfig, HtmlFetchable},
 };
 
 const SELECTOR_ERROR: &str = "Selector parse error";
+const DEFAULT_PROFILE: &str = "yandex_browser";
 
 #[derive(Debug)]
 pub struct YandexClient {
     origin: String,
     cookies_jar: Arc<MyJar>,
-    pub client: Client,
     pub config: FetcherConfig,
 }
 
 impl YandexClient {
-    fn gen_headers() -> HeaderMap {
-        let mut headers = HeaderMap::new();
-        headers.insert(USER_AGENT, "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/85.0.4183.102 YaBrowser/20.9.3.189 (beta) Yowser/2.5 Safari/537.36".parse().unwrap());
-        headers.insert(
-            ACCEPT,
-            "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8"
-                .parse()
-                .unwrap(),
-        );
-        headers.insert(
-            ACCEPT_LANGUAGE,
-            "ru,en;q=0.9,zh;q=0.8,nl;q=0.7,es;q=0.6".parse().unwrap(),
-        );
-        headers.insert(ACCEPT_ENCODING, "identity".parse().unwrap());
-        headers.insert(CONNECTION, "keep-alive".parse().unwrap());
-        headers
-    }
-
-    pub fn new(config: FetcherConfig) -> Self {
-        let url: Url = config.url.parse().unwrap();
-        let cookies_jar = Arc::new(MyJar::new(url.host().unwrap().to_string()));
-        YandexClient {
+    pub fn new(config: FetcherConfig) -> Result<Self> {
+        let url: Url = config.url.parse()?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("Url {} has no host", url))?;
+        let cookies_jar = Arc::new(MyJar::new(&config.cookies, host.to_string())?);
+        Ok(YandexClient {
             origin: url.origin().unicode_serialization(),
-            cookies_jar: cookies_jar.clone(),
-            client: Self::build_client(cookies_jar),
+            cookies_jar,
             config,
-        }
+        })
     }
 
-    fn build_client(cookies_jar: Arc<MyJar>) -> Client {
-        Client::builder()
-            .cookie_provider(cookies_jar)
-            .default_headers(Self::gen_headers())
-            // .proxy(reqwest::Proxy::all("localhost:8888").unwrap()).danger_accept_invalid_certs(true)
-            .timeout(Duration::from_secs(10))
-            .build()
-            .unwrap()
+    /// Clients are built per request since every request may go through another proxy,
+    /// the cookie jar is shared between all of them.
+    fn build_client(&self) -> Result<AimClient> {
+        let builder = Client::builder()
+            .cookie_provider(self.cookies_jar.clone())
+            .timeout(Duration::from_secs(10));
+        AimClient::new(&self.config, builder, Some(DEFAULT_PROFILE))
     }
 
-    async fn get_captcha_image(&self, resp: Response) -> Result<String> {
-        let tree = Html::parse_document(&resp.text().await?);
+    fn get_captcha_image(&self, page: &str) -> Result<String> {
+        let tree = Html::parse_document(page);
 
         let selector =
             Selector::parse(".AdvancedCaptcha-Image").map_err(|_| anyhow!(SELECTOR_ERROR))?;
@@ -84,11 +67,11 @@ impl YandexClient {
         Ok(action_path)
     }
 
-    async fn crack_captcha(&self, action: &str) -> Result<String> {
+    async fn crack_captcha(&self, client: &AimClient, action: &str) -> Result<String> {
         let action_path = self.origin.to_owned() + action;
-        let resp = self.client.get(action_path).send().await?;
+        let resp = client.get(&action_path).await?;
 
-        let action_path = self.get_captcha_image(resp).await?;
+        let action_path = self.get_captcha_image(&client.read_text(resp).await?)?;
         println!("Enter captcha:");
         let mut guess = String::new();
         io::stdin()
@@ -96,11 +79,21 @@ impl YandexClient {
             .expect("Failed to read line");
 
         let params = [("rep", guess)];
-        let resp = self.client.post(action_path).form(&params).send().await?;
-        Ok(resp.text().await?)
+        let resp = client
+            .send(client.client.post(action_path).form(&params))
+            .await?;
+        client.read_text(resp).await
+    }
+
+    async fn fetch(&self) -> Result<String> {
+        let client = self.build_client()?;
+        let text = client
+            .read_text(client.get(&self.config.url).await?)
+            .await?;
+        self.captcha_loop(&client, text).await
     }
 
-    async fn captcha_loop(&self, text: String) -> Result<String> {
+    async fn captcha_loop(&self, client: &AimClient, text: String) -> Result<String> {
         let mut result: String = text.clone();
         let captcha_form_selector =
             Selector::parse(".CheckboxCaptcha-Form").map_err(|_| anyhow!(SELECTOR_ERROR))?;
@@ -111,8 +104,10 @@ impl YandexClient {
                 .next()
                 .and_then(|x| x.value().attr("action").unwrap().to_owned().into());
             if let Some(action) = action {
-                result = self.crack_captcha(&action).await?;
-                self.cookies_jar.store_cookies()?;
+                if let Some(lease) = client.lease() {
+                    lease.captcha();
+                }
+                result = self.crack_captcha(client, &action).await?;
             } else {
                 return Ok(result);
             }
@@ -126,16 +121,24 @@ impl Fetchable for YandexClient {
         self
     }
 
-    async fn retrieve(&self) -> Result<Html> {
-        let resp = self.client.get(self.config.url.clone()).send().await?;
-        let text = resp.text().await?;
-        let result = self.captcha_loop(text).await?;
-        Ok(Html::parse_document(&result))
-    }
-
     fn config(&self) -> &FetcherConfig {
         &self.config
     }
+
+    async fn fetch(&self) -> Result<FetchResults> {
+        self.fetch_page().await
+    }
+}
+
+#[async_trait]
+impl HtmlFetchable for YandexClient {
+    async fn retrieve(&self) -> Result<Html> {
+        let result = self.fetch().await;
+        // once per retrieve, the cookies the server set are kept even if the page didn't come
+        let jar = self.cookies_jar.clone();
+        tokio::task::spawn_blocking(move || jar.store_cookies()).await??;
+        Ok(Html::parse_document(&result?))
+    }
 }
 
 #[cfg(test)]
@@ -1,8 +1,50 @@
 use std::sync::Arc;
 
-use tokio_postgres::{Client, Error, NoTls, Statement};
+use serde::Deserialize;
+use tokio_postgres::{Client, NoTls, Statement};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+use super::config_parser::parse_global_config;
+
+const CONFIG_PATH: &str = "config/postgres.yaml";
+
+/// Connection settings from `config/postgres.yaml`, the password usually comes from
+/// `${POSTGRES_PASSWORD}` or a `${file:...}` secret.
+#[derive(Deserialize, Debug)]
+pub struct PgConfig {
+    #[serde(default = "PgConfig::default_host")]
+    pub host: String,
+    #[serde(default = "PgConfig::default_user")]
+    pub user: String,
+    pub password: String,
+    #[serde(default)]
+    pub dbname: Option<String>,
+}
+
+impl PgConfig {
+    fn default_host() -> String {
+        "localhost".to_string()
+    }
+
+    fn default_user() -> String {
+        "postgres".to_string()
+    }
+
+    fn connection_string(&self) -> String {
+        let quote = |value: &str| format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"));
+        let mut params = format!(
+            "host={} user={} password={}",
+            quote(&self.host),
+            quote(&self.user),
+            quote(&self.password)
+        );
+        if let Some(dbname) = &self.dbname {
+            params.push_str(&format!(" dbname={}", quote(dbname)));
+        }
+        params
+    }
+}
 
 #[derive(Clone)]
 pub struct PgCollector {
@@ -11,10 +53,11 @@ pub struct PgCollector {
 }
 
 impl PgCollector {
-    pub async fn new() -> Result<Self, Error> {
+    pub async fn new() -> Result<Self> {
+        let config: PgConfig = parse_global_config(CONFIG_PATH)
+            .ok_or_else(|| anyhow!("Postgres needs a valid {}", CONFIG_PATH))?;
         let (client, connection) =
-            tokio_postgres::connect("host=localhost user=postgres password=password", NoTls)
-                .await?;
+            tokio_postgres::connect(&config.connection_string(), NoTls).await?;
         tokio::spawn(async move {
             if let Err(e) = connection.await {
                 eprintln!("Database connection error: {}", e);
@@ -23,7 +66,7 @@ impl PgCollector {
 
         client
             .query(
-                "CREATE TABLE history
+                "CREATE TABLE IF NOT EXISTS history
                     (
                         id SERIAL PRIMARY KEY,
                         data VARCHAR(1024)  NOT NULL,
@@ -31,18 +74,26 @@ impl PgCollector {
                     )",
                 &[],
             )
-            .await;
+            .await?;
+        client
+            .query(
+                "ALTER TABLE history ADD COLUMN IF NOT EXISTS aim_id VARCHAR(256)",
+                &[],
+            )
+            .await?;
 
         Ok(PgCollector {
             save_query: client
-                .prepare("INSERT INTO history (data) VALUES ($1)")
+                .prepare("INSERT INTO history (aim_id, data) VALUES ($1, $2)")
                 .await?,
             client: Arc::new(client),
         })
     }
 
-    pub async fn store(&self, data: String) -> Result<()> {
-        self.client.query(&self.save_query, &[&data]).await?;
+    pub async fn store(&self, aim_id: &str, data: String) -> Result<()> {
+        self.client
+            .query(&self.save_query, &[&aim_id, &data])
+            .await?;
         Ok(())
     }
 }
@@ -0,0 +1,98 @@
+use async_trait::async_trait;
+
+pub const USAGE: &str = "/list - aims\n\
+    /status - last results and errors\n\
+    /run <aim> - fetch the aim now\n\
+    /pause <aim>, /resume <aim> - stop and restart fetching the aim\n\
+    /history <aim> <item> - last values of the item\n\
+    /add <url> [selector or text] - new aim reading the value the selector or the text points to\n\
+    /confirm [id] - write the new aim, /cancel - drop it";
+
+/// Bot command, `/run@my_bot kettle` is taken as well as `/run kettle`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    List,
+    Status,
+    Run(String),
+    Pause(String),
+    Resume(String),
+    History(String, String),
+    /// Page url and a CSS selector or a piece of text, asked for when missing
+    Add(String, Option<String>),
+    Confirm(Option<String>),
+    Cancel,
+    /// Message that isn't a command, an answer in the `/add` conversation
+    Text(String),
+}
+
+impl Command {
+    /// `None` for empty texts, an error with the usage for wrong commands.
+    pub fn parse(text: &str) -> Option<Result<Command, String>> {
+        let mut words = text.split_whitespace();
+        let first = words.next()?;
+        let name = match first.strip_prefix('/') {
+            Some(name) => name,
+            None => return Some(Ok(Command::Text(text.trim().to_string()))),
+        };
+        let name = name.split('@').next().unwrap_or_default();
+        let args: Vec<_> = words.map(str::to_string).collect();
+        let command = match (name, args.as_slice()) {
+            ("help" | "start", _) => Command::Help,
+            ("list", []) => Command::List,
+            ("status", []) => Command::Status,
+            ("run", [aim]) => Command::Run(aim.clone()),
+            ("pause", [aim]) => Command::Pause(aim.clone()),
+            ("resume", [aim]) => Command::Resume(aim.clone()),
+            ("history", [aim, item]) => Command::History(aim.clone(), item.clone()),
+            ("add", [url]) => Command::Add(url.clone(), None),
+            ("add", [url, query @ ..]) => Command::Add(url.clone(), Some(query.join(" "))),
+            ("confirm", []) => Command::Confirm(None),
+            ("confirm", [id]) => Command::Confirm(Some(id.clone())),
+            ("cancel", []) => Command::Cancel,
+            _ => return Some(Err(format!("I don't know {}\n{}", text.trim(), USAGE))),
+        };
+        Some(Ok(command))
+    }
+}
+
+/// Carries out commands coming to the bot, the reply is sent back to the chat unless empty.
+#[async_trait]
+pub trait CommandHandler: Send + Sync {
+    async fn handle(&self, chat: &str, command: Command) -> String;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("/list"), Some(Ok(Command::List)));
+        assert_eq!(
+            Command::parse("/run@big_brother_bot  kettle "),
+            Some(Ok(Command::Run("kettle".to_string())))
+        );
+        assert_eq!(
+            Command::parse("/history kettle price"),
+            Some(Ok(Command::History(
+                "kettle".to_string(),
+                "price".to_string()
+            )))
+        );
+        assert!(Command::parse("/pause").unwrap().is_err());
+        assert!(Command::parse("/dance").unwrap().is_err());
+        assert_eq!(
+            Command::parse("/add https://shop.example/1 990 ₽"),
+            Some(Ok(Command::Add(
+                "https://shop.example/1".to_string(),
+                Some("990 ₽".to_string())
+            )))
+        );
+        assert_eq!(
+            Command::parse(" span.price "),
+            Some(Ok(Command::Text("span.price".to_string())))
+        );
+        assert_eq!(Command::parse("  "), None);
+    }
+}
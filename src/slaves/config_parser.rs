@@ -1,49 +1,208 @@
-use std::{fs, path::Path};
+use std::{collections::HashSet, env, fs, path::Path};
 
 use anyhow::{anyhow, Context, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_yaml::Value;
 
 use super::{
-    clients::yandex::client::YandexClient,
-    fetchers::{Fetchable, FetcherConfig, SimpleFetcher},
+    clients::{file::FileFetcher, json::JsonFetcher, yandex::client::YandexClient},
+    fetchers::{ClientType, Fetchable, FetcherConfig, SimpleFetcher},
+    templates::AimTemplate,
 };
 
+#[allow(dead_code)]
 pub fn parse_yaml(config_file: &str) -> Result<Box<dyn Fetchable + Sync>> {
-    let content = fs::read_to_string(config_file).unwrap();
-    let config: FetcherConfig = serde_yaml::from_str(&content)?;
+    let mut config: FetcherConfig = load_yaml(Path::new(config_file))?;
+    if config.id.is_empty() {
+        config.id = file_stem(Path::new(config_file)).to_string();
+    }
+    build_fetcher(config)
+}
+
+fn file_stem(path: &Path) -> &str {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+}
+
+/// Applies `f` to every string of the document, mapping keys included.
+pub fn map_strings(value: &Value, f: &mut impl FnMut(&str) -> Result<String>) -> Result<Value> {
+    Ok(match value {
+        Value::String(text) => Value::String(f(text)?),
+        Value::Sequence(items) => Value::Sequence(
+            items
+                .iter()
+                .map(|item| map_strings(item, f))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Mapping(map) => Value::Mapping(
+            map.iter()
+                .map(|(key, value)| Ok((map_strings(key, f)?, map_strings(value, f)?)))
+                .collect::<Result<_>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+fn secret(reference: &str) -> Result<String> {
+    match reference.strip_prefix("file:") {
+        Some(path) => Ok(fs::read_to_string(path)
+            .with_context(|| format!("Secret file {} can't be read", path))?
+            .trim_end()
+            .to_string()),
+        None => env::var(reference)
+            .map_err(|_| anyhow!("Environment variable {} is not set", reference)),
+    }
+}
+
+/// Replaces `${VAR}` with the environment variable and `${file:path}` with the content of
+/// the file, so secrets don't have to be stored in configs. `$${` gives a literal `${`.
+pub fn interpolate(text: &str) -> Result<String> {
+    let mut interpolated = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            interpolated.push_str(&rest[..start - 1]);
+            interpolated.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed ${{ in {}", text))?;
+        interpolated.push_str(&rest[..start]);
+        interpolated.push_str(&secret(rest[start + 2..start + end].trim())?);
+        rest = &rest[start + end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Documents of a config file with `${...}` references resolved. The format is chosen by
+/// the extension: YAML (several `---` separated documents allowed), TOML or JSON.
+pub fn load_documents(path: &Path) -> Result<Vec<Value>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| anyhow!("Path has no extension"))?;
+    let documents = match ext {
+        "yaml" | "yml" => serde_yaml::Deserializer::from_str(&content)
+            .map(Value::deserialize)
+            .filter(|document| !matches!(document, Ok(Value::Null)))
+            .collect::<Result<_, _>>()?,
+        "toml" => vec![toml::from_str(&content)?],
+        "json" => vec![serde_json::from_str(&content)?],
+        _ => {
+            return Err(anyhow!(
+                "I can only parse .yaml, .yml, .toml and .json files"
+            ))
+        }
+    };
+    documents
+        .iter()
+        .map(|document| map_strings(document, &mut |text| interpolate(text)))
+        .collect::<Result<_>>()
+        .with_context(|| format!("In {:?}", path))
+}
+
+/// Reads a single document config with `${...}` references resolved.
+pub fn load_yaml<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    match load_documents(path)?.as_slice() {
+        [document] => Ok(serde_yaml::from_value(document.clone())?),
+        documents => Err(anyhow!(
+            "{:?} has {} documents, expected one",
+            path,
+            documents.len()
+        )),
+    }
+}
+
+fn build_fetcher(config: FetcherConfig) -> Result<Box<dyn Fetchable + Sync>> {
+    // only pages read over plain http are crawled and paginated
+    let simple = config.client_type == ClientType::Simple && !FileFetcher::handles(&config.url);
+    if !simple && (config.follow.is_some() || config.pagination.is_some()) {
+        return Err(anyhow!(
+            "Aim {} is {:?}, follow and pagination are only supported by Simple aims",
+            config.id,
+            config.client_type
+        ));
+    }
     let fetcher: Box<dyn Fetchable + Sync> = match config.client_type {
-        super::fetchers::ClientType::Simple => Box::new(SimpleFetcher { config }),
-        super::fetchers::ClientType::Yandex => Box::new(YandexClient::new(config)),
+        ClientType::File => Box::new(FileFetcher { config }),
+        ClientType::Simple if FileFetcher::handles(&config.url) => Box::new(FileFetcher { config }),
+        ClientType::Simple => Box::new(SimpleFetcher { config }),
+        ClientType::Yandex => Box::new(YandexClient::new(config)?),
+        ClientType::Json => Box::new(JsonFetcher { config }),
     };
     Ok(fetcher)
 }
 
+/// Parses an optional process wide config such as `config/proxies.yaml`.
+/// A missing file gives `None`, a broken one is reported and ignored.
+pub fn parse_global_config<T: DeserializeOwned>(path: &str) -> Option<T> {
+    if !Path::new(path).exists() {
+        return None;
+    }
+    load_yaml(Path::new(path))
+        .map_err(|err| eprintln!("Couldn't load {}: {:?}", path, err))
+        .ok()
+}
+
+/// Aims of one file, one per document or, for aim templates, per parameter set.
+/// Aims without an `id` get the file stem, numbered from 1 when the file has several aims.
+pub fn parse_aim_file(path: &Path) -> Result<Vec<Box<dyn Fetchable + Sync>>> {
+    let mut configs: Vec<FetcherConfig> = vec![];
+    for document in load_documents(path)? {
+        if AimTemplate::is_template(&document) {
+            let template: AimTemplate = serde_yaml::from_value(document)?;
+            configs.extend(template.expand(path.parent().unwrap_or_else(|| Path::new(".")))?);
+        } else {
+            configs.push(serde_yaml::from_value(document)?);
+        }
+    }
+    let stem = file_stem(path);
+    let numbered = configs.len() > 1;
+    for (index, config) in configs.iter_mut().enumerate() {
+        if config.id.is_empty() {
+            config.id = match numbered {
+                true => format!("{}-{}", stem, index + 1),
+                false => stem.to_string(),
+            };
+        }
+    }
+    configs.into_iter().map(build_fetcher).collect()
+}
+
 pub fn parse_config_dir(dir_str: &str) -> Vec<Box<dyn Fetchable + Sync>> {
     let dir = Path::new(dir_str);
     let mut fetchers: Vec<Box<dyn Fetchable + Sync>> = vec![];
-    let files = fs::read_dir(dir).unwrap();
+    let mut ids = HashSet::new();
+    let mut files: Vec<_> = fs::read_dir(dir).unwrap().collect();
+    // keep the order of aims stable between runs
+    files.sort_by_key(|dir_entry| dir_entry.as_ref().map(|dir_entry| dir_entry.path()).ok());
     for dir_entry in files {
         let result = dir_entry.map_err(From::from).and_then(|dir_entry| {
             let path = dir_entry.path();
-            let parse_file = || -> Result<Box<dyn Fetchable + Sync>> {
-                let ext = path
-                    .extension()
-                    .ok_or_else(|| anyhow!("Path has no extension"))?;
-                if ext == "yaml" {
-                    parse_yaml(
-                        path.to_str()
-                            .ok_or_else(|| anyhow!("Path to str conversion error"))?,
-                    )
-                } else {
-                    Err(anyhow!("I can only parse .yaml files"))
-                }
-            };
-            parse_file().with_context(|| format!("Error occured with {:?}", path))
+            parse_aim_file(&path).with_context(|| format!("Error occured with {:?}", path))
         });
 
-        if let Ok(config) = result {
-            fetchers.push(config);
-        } else {
-            eprintln!("{:?}", result);
+        match result {
+            Ok(configs) => {
+                for fetcher in configs {
+                    let id = &fetcher.config().id;
+                    if ids.insert(id.clone()) {
+                        fetchers.push(fetcher);
+                    } else {
+                        eprintln!(
+                            "Duplicate aim id {}, the aim with url {} is skipped",
+                            id,
+                            fetcher.config().url
+                        );
+                    }
+                }
+            }
+            Err(err) => eprintln!("{:?}", err),
         }
     }
     fetchers
@@ -51,18 +210,21 @@ pub fn parse_config_dir(dir_str: &str) -> Vec<Box<dyn Fetchable + Sync>> {
 
 #[cfg(test)]
 pub mod tests {
+    use std::{env, fs};
+
     use crate::slaves::{
-        config_parser::{parse_config_dir, parse_yaml},
+        config_parser::{build_fetcher, interpolate, parse_config_dir, parse_yaml},
         fetchers::{ClientType, FetchItem, FetchItemType, FetcherConfig, SimpleFetcher},
     };
 
     fn gen_config1() -> SimpleFetcher {
         let item1 = FetchItem {
             name: "item1".to_string(),
-            path: "body > div > p:nth-child(3) > a".to_string(),
+            path: vec!["body > div > p:nth-child(3) > a".to_string()],
             primary: false,
             item_type: FetchItemType::Text,
             related: vec![],
+            ..Default::default()
         };
 
         let item2 = FetchItem {
@@ -78,9 +240,11 @@ pub mod tests {
         };
 
         let config = FetcherConfig {
+            id: "example".to_string(),
             client_type: ClientType::Simple,
             items: vec![item1, item2, item3],
             url: "http://example.com".to_string(),
+            ..Default::default()
         };
 
         SimpleFetcher { config }
@@ -89,10 +253,11 @@ pub mod tests {
     fn gen_config2() -> SimpleFetcher {
         let item_x = FetchItem {
             name: "entity_x".to_string(),
-            path: "body > div > p:nth-child(3) > a".to_string(),
+            path: vec!["body > div > p:nth-child(3) > a".to_string()],
             primary: false,
             item_type: FetchItemType::Text,
             related: vec![],
+            ..Default::default()
         };
         let item_y = FetchItem {
             name: "entity_y".to_string(),
@@ -107,9 +272,11 @@ pub mod tests {
         };
 
         let config = FetcherConfig {
+            id: "example2".to_string(),
             client_type: ClientType::Simple,
             items: vec![item_x, item_y, item_z],
             url: "http://another-example.com".to_string(),
+            ..Default::default()
         };
 
         SimpleFetcher { config }
@@ -143,4 +310,67 @@ pub mod tests {
         // configs[1].iter().zip(&config1).for_each(|(i1, i2)| assert_eq!(i1, i2));
         // configs[0].iter().zip(&config2).for_each(|(i1, i2)| assert_eq!(i1, i2));
     }
+
+    #[test]
+    fn test_follow_needs_simple_aim() {
+        let config = |client_type: &str| {
+            serde_yaml::from_str::<FetcherConfig>(&format!(
+                "id: listing\nclient_type: {}\nurl: http://example.com\nitems: []\n\
+                 pagination:\n  next: a.next\n",
+                client_type
+            ))
+            .unwrap()
+        };
+        assert!(build_fetcher(config("Simple")).is_ok());
+        let err = build_fetcher(config("Json")).unwrap_err();
+        assert!(err.to_string().contains("listing is Json"));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("BB_TEST_TOKEN", "123:abc");
+        fs::write("test/bb_test_secret", "s3cret\n").unwrap();
+        let with_file = interpolate("password=${file:test/bb_test_secret}");
+        fs::remove_file("test/bb_test_secret").unwrap();
+
+        assert_eq!(interpolate("bot${BB_TEST_TOKEN}/").unwrap(), "bot123:abc/");
+        assert_eq!(with_file.unwrap(), "password=s3cret");
+        assert_eq!(interpolate("cost: $${PRICE}").unwrap(), "cost: ${PRICE}");
+        assert!(interpolate("$5 ${").is_err());
+        let err = interpolate("${BB_TEST_MISSING}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Environment variable BB_TEST_MISSING is not set"
+        );
+        assert!(interpolate("${file:test/no_such_secret}").is_err());
+    }
+
+    #[test]
+    fn test_config_formats() {
+        let fetchers = parse_config_dir("test/formats");
+        let urls: Vec<_> = fetchers
+            .iter()
+            .map(|fetcher| fetcher.config().url.as_str())
+            .collect();
+        assert_eq!(
+            urls,
+            vec![
+                "http://json.example.com",
+                "http://toml.example.com",
+                "http://yml.example.com",
+                "http://first.example.com",
+                "http://second.example.com",
+            ]
+        );
+        let ids: Vec<_> = fetchers
+            .iter()
+            .map(|fetcher| fetcher.config().id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["aim", "toml", "yml", "many-1", "many-2"]);
+        assert_eq!(fetchers[0].config().items[0].path, vec!["h1".to_string()]);
+        assert_eq!(
+            fetchers[1].config().items[0].path,
+            vec!["h1".to_string(), "h2".to_string()]
+        );
+    }
 }
@@ -0,0 +1,263 @@
+use std::collections::{HashSet, VecDeque};
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+
+use super::{
+    clients::{cache::NotModified, http::AimClient},
+    fetchers::{FetchItem, FetchResults, HtmlFetchable},
+    pagination,
+};
+
+/// Follows links found on the aim page and extracts `items` from every linked page.
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct FollowConfig {
+    /// CSS selector of the `<a href>` elements to follow
+    pub links: String,
+    /// Items extracted from each linked page, one result set per page
+    pub items: Vec<FetchItem>,
+    /// How many link levels deep to go, `links` is applied to the linked pages too when above 1
+    #[serde(default = "FollowConfig::default_depth")]
+    pub depth: u32,
+    #[serde(default = "FollowConfig::default_max_pages")]
+    pub max_pages: usize,
+    /// Skip links leading to other hosts
+    #[serde(default = "FollowConfig::default_same_domain")]
+    pub same_domain: bool,
+}
+
+impl FollowConfig {
+    fn default_depth() -> u32 {
+        1
+    }
+
+    fn default_max_pages() -> usize {
+        20
+    }
+
+    fn default_same_domain() -> bool {
+        true
+    }
+
+    /// Absolute links of the page without fragments, in document order.
+    fn links(&self, page_url: &Url, tree: &Html) -> Result<Vec<Url>> {
+        let selector = Selector::parse(&self.links)
+            .map_err(|x| anyhow!("Selector parsing errored {:?}", x))?;
+        Ok(tree
+            .select(&selector)
+            .filter_map(|link| link.value().attr("href"))
+            .filter_map(|href| page_url.join(href).ok())
+            .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+            .filter(|url| !self.same_domain || url.host_str() == page_url.host_str())
+            .map(|mut url| {
+                url.set_fragment(None);
+                url
+            })
+            .collect())
+    }
+}
+
+/// Result set of the aim page itself, all its pages when paginated, followed by one set per
+/// linked page. Pages are visited breadth first and each url at most once.
+pub async fn crawl(
+    fetcher: &(impl HtmlFetchable + ?Sized + Sync),
+    follow: &FollowConfig,
+    client: &AimClient,
+) -> Result<Vec<FetchResults>> {
+    let config = fetcher.config();
+    let start_url = Url::parse(&config.url)?;
+    let listing = pagination::read_pages(client, config, |url, tree| {
+        let found = fetcher.extract(tree);
+        let links = follow.links(url, tree);
+        let has_data = found.iter().any(Option::is_some)
+            || links.as_ref().map_or(true, |links| !links.is_empty());
+        Some((found, links)).filter(|_| has_data)
+    })
+    .await?;
+    let mut fetched = vec![vec![]];
+    let mut links = vec![];
+    for (found, page_links) in listing {
+        fetched[0].extend(found);
+        links.extend(page_links?);
+    }
+
+    let mut visited = HashSet::from([start_url]);
+    let mut queue = VecDeque::new();
+    for url in links {
+        if visited.insert(url.clone()) {
+            queue.push_back((url, 1));
+        }
+    }
+
+    let mut pages = 0;
+    while let Some((url, depth)) = queue.pop_front() {
+        if pages >= follow.max_pages {
+            break;
+        }
+        pages += 1;
+        let text = match client.get_text(url.as_str()).await {
+            Ok(text) => text,
+            Err(err) => match err.downcast::<NotModified>() {
+                // an unchanged page still has its items and links
+                Ok(NotModified(text)) => text,
+                Err(err) => {
+                    eprintln!("Couldn't follow {}: {:?}", url, err);
+                    continue;
+                }
+            },
+        };
+        let tree = Html::parse_document(&text);
+        fetched.push(fetcher.extract_items(&follow.items, &tree));
+        if depth < follow.depth {
+            for link in follow.links(&url, &tree)? {
+                if visited.insert(link.clone()) {
+                    queue.push_back((link, depth + 1));
+                }
+            }
+        }
+    }
+    Ok(fetched)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use reqwest::Client;
+
+    use crate::slaves::{
+        clients::{
+            cassette::CassetteConfig,
+            http::{
+                tests::{response, serve},
+                AimClient,
+            },
+        },
+        fetchers::{
+            FetchItem, FetchResults, Fetchable, FetcherConfig, FoundItemContent, SimpleFetcher,
+        },
+    };
+
+    use super::{crawl, FollowConfig};
+
+    fn page(body: &str) -> String {
+        response("200 OK", &["content-type: text/html"], body)
+    }
+
+    fn follow(depth: u32) -> FollowConfig {
+        FollowConfig {
+            links: "a.card".to_string(),
+            items: vec![FetchItem {
+                name: "title".to_string(),
+                path: vec!["h1".to_string()],
+                primary: true,
+                ..Default::default()
+            }],
+            depth,
+            max_pages: 20,
+            same_domain: true,
+        }
+    }
+
+    fn fetcher(url: String) -> SimpleFetcher {
+        SimpleFetcher {
+            config: FetcherConfig {
+                url,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Titles of the linked pages, the aim page itself has no items.
+    fn titles(fetched: &[FetchResults]) -> Vec<FoundItemContent> {
+        assert!(fetched[0].is_empty());
+        fetched
+            .iter()
+            .skip(1)
+            .map(|results| results[0].as_ref().unwrap().content.clone())
+            .collect()
+    }
+
+    fn strings(values: &[&str]) -> Vec<FoundItemContent> {
+        values
+            .iter()
+            .map(|value| FoundItemContent::Str(value.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_crawl() {
+        let listing = r#"<a class="card" href="/a">A</a><a class="card" href="/a#reviews">A</a>
+            <a class="card" href="b">B</a><a class="card" href="http://other.example/c">C</a>"#;
+        let (url, server) = serve(vec![
+            page(listing),
+            page(r#"<h1>Kettle</h1><a class="card" href="/deep">Deep</a>"#),
+            page("<h1>Toaster</h1>"),
+        ])
+        .await;
+        let fetcher = fetcher(url);
+
+        let client = AimClient::new(&fetcher.config, Client::builder(), None).unwrap();
+        let fetched = crawl(&fetcher, &follow(1), &client).await.unwrap();
+        server.await.unwrap();
+
+        assert_eq!(titles(&fetched), strings(&["Kettle", "Toaster"]));
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_page() {
+        let listing = page(r#"<a class="card" href="/a">A</a>"#);
+        let kettle = r#"<h1>Kettle</h1><a class="card" href="/deep">Deep</a>"#;
+        let (url, server) = serve(vec![
+            listing.clone(),
+            response(
+                "200 OK",
+                &["content-type: text/html", "etag: \"a\""],
+                kettle,
+            ),
+            page("<h1>Deep</h1>"),
+            listing,
+            response("304 Not Modified", &[], ""),
+            page("<h1>Deeper</h1>"),
+        ])
+        .await;
+        let fetcher = fetcher(url);
+
+        let client = AimClient::new(&fetcher.config, Client::builder(), None).unwrap();
+        crawl(&fetcher, &follow(2), &client).await.unwrap();
+        let fetched = crawl(&fetcher, &follow(2), &client).await.unwrap();
+        server.await.unwrap();
+
+        // the unchanged page is read again and its links are followed
+        assert_eq!(titles(&fetched), strings(&["Kettle", "Deeper"]));
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = "test/cassettes_follow";
+        let _ = fs::remove_dir_all(dir);
+        let (url, server) = serve(vec![
+            page(r#"<a class="card" href="/a">A</a><a class="card" href="/b">B</a>"#),
+            page("<h1>Kettle</h1>"),
+            page("<h1>Toaster</h1>"),
+        ])
+        .await;
+        let aim = |cassette| SimpleFetcher {
+            config: FetcherConfig {
+                follow: Some(follow(1)),
+                cassette: Some(cassette),
+                ..fetcher(url.clone()).config
+            },
+        };
+
+        let recorded = aim(CassetteConfig::record(dir)).fetch_all().await.unwrap();
+        server.await.unwrap();
+        let replayed = aim(CassetteConfig::replay(dir)).fetch_all().await;
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(titles(&recorded), strings(&["Kettle", "Toaster"]));
+        assert_eq!(replayed.unwrap(), recorded);
+    }
+}
@@ -1,15 +1,306 @@
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::Instant,
+};
 
 use super::{
+    clients::{limits::concurrency_limit, proxy::ProxyPool},
+    commands::{Command, CommandHandler, USAGE},
     config_parser::parse_config_dir,
-    fetchers::{Fetchable, FoundItem},
+    drafts::AimDraft,
+    fetchers::{AimInfo, AimResult, Fetchable, FoundItem},
     saver::Saver,
+    serializer::{serialize_all, SerType},
 };
 
+/// Values of an item kept for `/history`.
+const HISTORY_LEN: usize = 10;
+
+/// What the bot commands see and change in a running daemon.
+#[derive(Default)]
+struct DaemonState {
+    aims: Vec<AimInfo>,
+    paused: BTreeSet<String>,
+    /// Results of the last fetch of every aim or the error it failed with
+    last: BTreeMap<String, (SystemTime, Result<Vec<AimResult>, String>)>,
+    /// Changes of every item, keyed by aim id and item name
+    history: BTreeMap<(String, String), VecDeque<(SystemTime, String)>>,
+}
+
+impl DaemonState {
+    /// Remembers the aims and leaves out the paused ones.
+    fn scheduled(
+        &mut self,
+        fetchers: Vec<Box<dyn Fetchable + Sync>>,
+    ) -> Vec<Box<dyn Fetchable + Sync>> {
+        self.aims = fetchers
+            .iter()
+            .map(|fetcher| fetcher.config().info())
+            .collect();
+        fetchers
+            .into_iter()
+            .filter(|fetcher| !self.paused.contains(&fetcher.config().id))
+            .collect()
+    }
+
+    fn record(&mut self, ids: &[String], fetched: &[AimResult], errors: BTreeMap<String, String>) {
+        let now = SystemTime::now();
+        for id in ids {
+            let results: Vec<_> = fetched
+                .iter()
+                .filter(|result| &result.aim.id == id)
+                .cloned()
+                .collect();
+            let mut values = BTreeMap::<_, Vec<_>>::new();
+            for item in results.iter().flat_map(|result| &result.items) {
+                values
+                    .entry(item.fetch_item.name.clone())
+                    .or_default()
+                    .push(item.content.text());
+            }
+            for (name, values) in values {
+                let history = self.history.entry((id.clone(), name)).or_default();
+                let value = values.join(", ");
+                if history.back().map(|(_, last)| last) != Some(&value) {
+                    history.push_back((now, value));
+                }
+                if history.len() > HISTORY_LEN {
+                    history.pop_front();
+                }
+            }
+            let last = match errors.get(id) {
+                Some(err) => Err(err.clone()),
+                None => Ok(results),
+            };
+            self.last.insert(id.clone(), (now, last));
+        }
+    }
+
+    fn knows(&self, aim: &str) -> bool {
+        self.aims.iter().any(|info| info.id == aim)
+    }
+
+    fn list(&self) -> String {
+        if self.aims.is_empty() {
+            return "There are no aims yet".to_string();
+        }
+        self.aims
+            .iter()
+            .map(|aim| {
+                let mut line = aim.id.clone();
+                if let Some(name) = &aim.name {
+                    line += &format!(" - {}", name);
+                }
+                if !aim.tags.is_empty() {
+                    line += &format!(" [{}]", aim.tags.join(", "));
+                }
+                if self.paused.contains(&aim.id) {
+                    line += " (paused)";
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn status(&self) -> String {
+        if self.aims.is_empty() {
+            return "There are no aims yet".to_string();
+        }
+        self.aims
+            .iter()
+            .map(|aim| {
+                let status = match self.last.get(&aim.id) {
+                    None => "not fetched yet".to_string(),
+                    Some((at, Err(err))) => {
+                        format!("failed at {}: {}", httpdate::fmt_http_date(*at), err)
+                    }
+                    Some((at, Ok(results))) if results.is_empty() => {
+                        format!("nothing found at {}", httpdate::fmt_http_date(*at))
+                    }
+                    Some((at, Ok(results))) => {
+                        // the line starts with the label already
+                        let items = results
+                            .iter()
+                            .map(|result| AimResult {
+                                aim: AimInfo::default(),
+                                items: result.items.clone(),
+                            })
+                            .collect();
+                        format!(
+                            "{} at {}",
+                            serialize_all(items, SerType::Plain),
+                            httpdate::fmt_http_date(*at)
+                        )
+                    }
+                };
+                let paused = match self.paused.contains(&aim.id) {
+                    true => " (paused)",
+                    false => "",
+                };
+                format!("{}{}: {}", aim.label(), paused, status)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn history(&self, aim: &str, item: &str) -> String {
+        match self.history.get(&(aim.to_string(), item.to_string())) {
+            Some(history) => history
+                .iter()
+                .map(|(at, value)| format!("{}: {}", httpdate::fmt_http_date(*at), value))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => format!("No values of {} in aim {} yet", item, aim),
+        }
+    }
+}
+
+/// Where the `/add` conversation of a chat is.
+#[derive(Debug, Clone)]
+enum Draft {
+    /// Waiting for a selector or a piece of text
+    Url(String),
+    /// Waiting for `/confirm`, another selector or text is tried as well
+    Probed(AimDraft),
+}
+
+/// Longest value shown back while adding an aim.
+const SHOWN_VALUE_LEN: usize = 300;
+
+/// Takes the bot commands to a running daemon.
+#[derive(Clone)]
+pub struct DaemonHandle {
+    state: Arc<Mutex<DaemonState>>,
+    runs: mpsc::Sender<String>,
+    aims_dir: PathBuf,
+    /// `/add` conversations by chat
+    drafts: Arc<Mutex<BTreeMap<String, Draft>>>,
+}
+
+impl DaemonHandle {
+    fn new(state: Arc<Mutex<DaemonState>>, runs: mpsc::Sender<String>, aims_dir: &str) -> Self {
+        DaemonHandle {
+            state,
+            runs,
+            aims_dir: PathBuf::from(aims_dir),
+            drafts: Default::default(),
+        }
+    }
+
+    async fn probe(&self, chat: &str, url: String, query: String) -> String {
+        let (draft, reply) = match AimDraft::probe(&url, &query).await {
+            Ok(probed) => {
+                let mut value: String = probed.value.chars().take(SHOWN_VALUE_LEN).collect();
+                if value.len() < probed.value.len() {
+                    value.push('…');
+                }
+                let reply = format!(
+                    "{} gives {}\nSend /confirm to add the aim as {} or /confirm <id>, \
+                     another selector or text to try again or /cancel",
+                    probed.selector,
+                    value,
+                    probed.default_id()
+                );
+                (Draft::Probed(probed), reply)
+            }
+            Err(err) => {
+                let reply = format!(
+                    "Couldn't find it: {}\nSend another selector or text or /cancel",
+                    err
+                );
+                (Draft::Url(url), reply)
+            }
+        };
+        self.drafts.lock().unwrap().insert(chat.to_string(), draft);
+        reply
+    }
+
+    async fn confirm(&self, chat: &str, id: Option<String>) -> String {
+        let draft = match self.drafts.lock().unwrap().get(chat) {
+            Some(Draft::Probed(draft)) => draft.clone(),
+            _ => return "There is no aim to confirm, start with /add".to_string(),
+        };
+        let id = id.unwrap_or_else(|| draft.default_id());
+        if self.state.lock().unwrap().knows(&id) {
+            return format!("There is an aim {} already", id);
+        }
+        if let Err(err) = draft.write(&self.aims_dir, &id) {
+            return err.to_string();
+        }
+        self.drafts.lock().unwrap().remove(chat);
+        match self.runs.try_send(id.clone()) {
+            Ok(()) => format!("Aim {} is added and being fetched", id),
+            Err(_) => format!("Aim {} is added", id),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandHandler for DaemonHandle {
+    async fn handle(&self, chat: &str, command: Command) -> String {
+        let known = |aim: &str| self.state.lock().unwrap().knows(aim);
+        match command {
+            Command::Help => USAGE.to_string(),
+            Command::List => self.state.lock().unwrap().list(),
+            Command::Status => self.state.lock().unwrap().status(),
+            Command::Run(aim) | Command::Pause(aim) | Command::Resume(aim) if !known(&aim) => {
+                format!("There is no aim {}", aim)
+            }
+            // the daemon may be busy with a run, the command isn't kept waiting for it
+            Command::Run(aim) => match self.runs.try_send(aim.clone()) {
+                Ok(()) => format!("Fetching {}", aim),
+                Err(TrySendError::Full(_)) => {
+                    format!("Too many aims are waiting to be fetched, try {} later", aim)
+                }
+                Err(TrySendError::Closed(_)) => "The daemon isn't running".to_string(),
+            },
+            Command::Pause(aim) => {
+                self.state.lock().unwrap().paused.insert(aim.clone());
+                format!("{} is paused", aim)
+            }
+            Command::Resume(aim) => match self.state.lock().unwrap().paused.remove(&aim) {
+                true => format!("{} is resumed", aim),
+                false => format!("{} isn't paused", aim),
+            },
+            Command::History(aim, item) => self.state.lock().unwrap().history(&aim, &item),
+            Command::Add(url, None) => {
+                let draft = Draft::Url(url);
+                self.drafts.lock().unwrap().insert(chat.to_string(), draft);
+                "Send a CSS selector of the value or a piece of its text".to_string()
+            }
+            Command::Add(url, Some(query)) => self.probe(chat, url, query).await,
+            Command::Text(query) => {
+                let url = match self.drafts.lock().unwrap().get(chat) {
+                    Some(Draft::Url(url)) => url.clone(),
+                    Some(Draft::Probed(draft)) => draft.url.clone(),
+                    // not a part of a conversation
+                    None => return String::new(),
+                };
+                self.probe(chat, url, query).await
+            }
+            Command::Confirm(id) => self.confirm(chat, id).await,
+            Command::Cancel => match self.drafts.lock().unwrap().remove(chat) {
+                Some(_) => "The new aim is dropped".to_string(),
+                None => "There is nothing to cancel".to_string(),
+            },
+        }
+    }
+}
+
 pub struct FetchDaemon {
     interval: Duration,
     conf_path: String,
     saver: Saver,
+    state: Arc<Mutex<DaemonState>>,
 }
 
 impl FetchDaemon {
@@ -18,74 +309,190 @@ impl FetchDaemon {
             interval,
             conf_path,
             saver,
+            state: Default::default(),
         }
     }
 
+    #[allow(dead_code)]
     pub fn new_default(interval: Duration, saver: Saver) -> Self {
-        FetchDaemon {
-            interval,
-            conf_path: "aims".to_string(),
-            saver,
-        }
+        Self::new(interval, "aims".to_string(), saver)
     }
 
-    async fn fetch_data(fetchers: Vec<Box<impl Fetchable + ?Sized + Sync>>) -> Vec<Vec<FoundItem>> {
+    /// Results of the aims and, by aim id, the errors of those that couldn't be fetched.
+    async fn fetch_data(
+        fetchers: Vec<Box<impl Fetchable + ?Sized + Sync>>,
+    ) -> (Vec<AimResult>, BTreeMap<String, String>) {
         let mut pendind_tasks = vec![];
+        let limit = concurrency_limit();
         for fetcher in fetchers {
+            let limit = limit.clone();
             pendind_tasks.push(tokio::spawn(async move {
-                let future = fetcher.fetch();
-                if let Ok(data) = future.await {
-                    Some(data)
-                } else {
-                    println!("Couldn't fetch any data in {:?}", fetcher);
-                    None
+                let _permit = limit.acquire_owned().await;
+                let future = fetcher.fetch_all();
+                let aim = fetcher.config().info();
+                match future.await {
+                    Ok(data) => (aim, Ok(data)),
+                    Err(err) => {
+                        println!("Couldn't fetch any data in aim {}: {:?}", aim.id, err);
+                        (aim, Err(err.to_string()))
+                    }
                 }
             }));
         }
         let mut fetched_confs = vec![];
+        let mut errors = BTreeMap::new();
 
         for pending_task in pendind_tasks {
-            let fetched = pending_task
-                .await
-                .map(|list| list.into_iter().flatten().flatten().collect::<Vec<_>>());
-
-            if let Ok(data) = fetched {
-                if !data.is_empty() {
-                    fetched_confs.push(data)
+            // one result set per page, an aim reading several files gives several of them
+            let (aim, fetched) = match pending_task.await {
+                Ok((aim, Ok(fetched))) => (aim, fetched),
+                Ok((aim, Err(err))) => {
+                    errors.insert(aim.id, err);
+                    continue;
                 }
+                Err(_) => continue,
             };
+            for list in fetched {
+                let items = list.into_iter().flatten().collect::<Vec<_>>();
+                if !items.is_empty() {
+                    fetched_confs.push(AimResult {
+                        aim: aim.clone(),
+                        items,
+                    })
+                }
+            }
         }
 
-        fetched_confs
+        (fetched_confs, errors)
+    }
+
+    /// Fetches the aims and keeps what the bot commands report.
+    async fn run(&self, fetchers: Vec<Box<dyn Fetchable + Sync>>) -> Vec<AimResult> {
+        let ids: Vec<_> = fetchers
+            .iter()
+            .map(|fetcher| fetcher.config().id.clone())
+            .collect();
+        let (fetched, errors) = Self::fetch_data(fetchers).await;
+        self.state.lock().unwrap().record(&ids, &fetched, errors);
+        fetched
+    }
+
+    /// Items found only by a fallback selector, keyed by aim id, item name and the selector that matched.
+    fn fallback_warnings(fetched: &[AimResult]) -> BTreeMap<(String, String, String), String> {
+        fetched
+            .iter()
+            .flat_map(|result| {
+                result
+                    .items
+                    .iter()
+                    .flat_map(FoundItem::fallbacks)
+                    .map(move |item| (&result.aim, item))
+            })
+            .map(|(aim, item)| {
+                let name = &item.fetch_item.name;
+                let warning = format!(
+                    "Primary selector {} of {} in aim {} stopped matching, fallback {} is used",
+                    item.fetch_item.path[0],
+                    name,
+                    aim.label(),
+                    item.matched_path()
+                );
+                let key = (
+                    aim.id.clone(),
+                    name.clone(),
+                    item.matched_path().to_string(),
+                );
+                (key, warning)
+            })
+            .collect()
     }
 
     pub async fn start(self) {
+        let (runs, mut run_requests) = mpsc::channel(8);
+        let handle = DaemonHandle::new(self.state.clone(), runs, &self.conf_path);
+        if let Err(err) = self.saver.serve_commands(Arc::new(handle)).await {
+            eprintln!("{:?}", err);
+        }
+        // fallbacks already reported, a warning is sent again only after the primary recovers
+        let mut warned = BTreeSet::new();
         loop {
+            ProxyPool::probe_all().await;
             let fetchers = parse_config_dir(&self.conf_path[..]);
-            let fetched = Self::fetch_data(fetchers).await;
-            self.saver.push(fetched).await;
+            let fetchers = self.state.lock().unwrap().scheduled(fetchers);
+            let fetched = self.run(fetchers).await;
+            let warnings = Self::fallback_warnings(&fetched);
+            for (key, warning) in warnings.iter() {
+                let aim = fetched
+                    .iter()
+                    .map(|result| &result.aim)
+                    .find(|aim| aim.id == key.0);
+                if let Some(aim) = aim.filter(|_| !warned.contains(key)) {
+                    eprintln!("{}", warning);
+                    if let Err(err) = self.saver.warn(aim, warning.clone()).await {
+                        eprintln!("{:?}", err);
+                    }
+                }
+            }
+            warned = warnings.into_keys().collect();
+            if let Err(err) = self.saver.push(fetched).await {
+                eprintln!("{:?}", err);
+            }
             println!("Going to sleep for {} secs...", self.interval.as_secs());
-            tokio::time::sleep(self.interval).await;
+            let next_run = Instant::now() + self.interval;
+            // aims asked for with `/run` are fetched while waiting for the next scheduled run
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(next_run) => break,
+                    Some(aim) = run_requests.recv() => {
+                        let fetchers = parse_config_dir(&self.conf_path[..])
+                            .into_iter()
+                            .filter(|fetcher| fetcher.config().id == aim)
+                            .collect();
+                        let fetched = self.run(fetchers).await;
+                        if let Err(err) = self.saver.push(fetched).await {
+                            eprintln!("{:?}", err);
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::slaves::fetchers::{
-        ClientType, FetchItem, FetchItemType, FetcherConfig, FoundItem, FoundItemContent::*,
-        SimpleFetcher,
+    use std::{collections::BTreeMap, fs, path::Path, sync::Arc};
+
+    use tokio::sync::mpsc;
+
+    use crate::slaves::{
+        clients::{
+            cassette::CassetteConfig,
+            http::tests::{response, serve},
+        },
+        config_parser::parse_aim_file,
+        fetchers::{
+            AimInfo, AimResult, ClientType, FetchItem, FetchItemType, FetcherConfig, FoundItem,
+            FoundItemContent::*, SimpleFetcher,
+        },
     };
 
-    use super::FetchDaemon;
+    use super::{Command, CommandHandler, DaemonHandle, FetchDaemon};
+
+    fn items(fetched: Vec<AimResult>) -> Vec<Vec<FoundItem>> {
+        let mut items: Vec<_> = fetched.into_iter().map(|result| result.items).collect();
+        items.sort();
+        items
+    }
 
     fn gen_config2() -> Box<SimpleFetcher> {
         let item_x = FetchItem {
             name: "entity_x".to_string(),
-            path: "body > div > p:nth-child(3) > a".to_string(),
+            path: vec!["body > div > p:nth-child(3) > a".to_string()],
             primary: false,
             item_type: FetchItemType::Text,
             related: vec![],
+            ..Default::default()
         };
         let item_y = FetchItem {
             name: "entity_y".to_string(),
@@ -103,6 +510,8 @@ mod tests {
             client_type: ClientType::Simple,
             items: vec![item_x, item_y, item_z],
             url: "http://another-example.com".to_string(),
+            cassette: Some(CassetteConfig::replay("test/cassettes")),
+            ..Default::default()
         };
 
         Box::new(SimpleFetcher { config })
@@ -112,10 +521,11 @@ mod tests {
     async fn test_fetch_data() {
         let item1 = FetchItem {
             name: "item1".to_string(),
-            path: "body > div > p:nth-child(3) > a".to_string(),
+            path: vec!["body > div > p:nth-child(3) > a".to_string()],
             primary: false,
             item_type: FetchItemType::Text,
             related: vec![],
+            ..Default::default()
         };
 
         let item2 = FetchItem {
@@ -134,13 +544,14 @@ mod tests {
             client_type: ClientType::Simple,
             items: vec![item1.clone(), item2.clone(), item3.clone()],
             url: "http://example.com".to_string(),
+            cassette: Some(CassetteConfig::replay("test/cassettes")),
+            ..Default::default()
         };
         let config1 = Box::new(SimpleFetcher { config: config1 });
         let config2 = gen_config2();
 
         let aims = vec![config1, config2];
-        let mut fetched = FetchDaemon::fetch_data(aims).await;
-        fetched.sort();
+        let fetched = items(FetchDaemon::fetch_data(aims).await.0);
 
         let mut correct = vec![FoundItem {
             fetch_item: item3,
@@ -150,13 +561,16 @@ mod tests {
                     fetch_item: item1,
                     content: Str("More information...".to_string()),
                     related: vec![],
+                    selector: 0,
                 }),
                 Some(FoundItem {
                     fetch_item: item2,
                     content: Str("More information...".to_string()),
                     related: vec![],
+                    selector: 0,
                 }),
             ],
+            selector: 0,
         }];
         correct.sort();
 
@@ -167,15 +581,16 @@ mod tests {
     async fn test_class_fetch_item() {
         let translations = FetchItem {
             name: "translations".to_string(),
-            path: "#Content > div:nth-child(5)".to_string(),
+            path: vec!["#Content > div:nth-child(5)".to_string()],
             primary: true,
             item_type: FetchItemType::Class,
             related: vec![],
+            ..Default::default()
         };
 
         let banner = FetchItem {
             name: "banner".to_string(),
-            path: "#Content > div:nth-child(7)".to_string(),
+            path: vec!["#Content > div:nth-child(7)".to_string()],
             ..translations.clone()
         };
 
@@ -183,22 +598,28 @@ mod tests {
             client_type: ClientType::Simple,
             items: vec![translations.clone(), banner.clone()],
             url: "https://www.lipsum.com/".to_string(),
+            cassette: Some(CassetteConfig::replay("test/cassettes")),
+            ..Default::default()
         };
 
-        let mut fetched =
-            FetchDaemon::fetch_data(vec![Box::new(SimpleFetcher { config: config1 })]).await;
-        fetched.sort();
+        let fetched = items(
+            FetchDaemon::fetch_data(vec![Box::new(SimpleFetcher { config: config1 })])
+                .await
+                .0,
+        );
 
         let correct = vec![
             FoundItem {
                 fetch_item: translations,
                 content: Arr(vec!["boxed".to_string()]),
                 related: vec![],
+                selector: 0,
             },
             FoundItem {
                 fetch_item: banner,
                 content: Arr(vec!["boxed".to_string()]),
                 related: vec![],
+                selector: 0,
             },
         ];
         let mut correct = vec![correct];
@@ -211,29 +632,35 @@ mod tests {
     async fn test_mixed_items() {
         let translations = FetchItem {
             name: "translations".to_string(),
-            path: "#Content > div:nth-child(5)".to_string(),
+            path: vec!["#Content > div:nth-child(5)".to_string()],
             primary: false,
             item_type: FetchItemType::Class,
             related: vec![],
+            ..Default::default()
         };
 
         let item1 = FetchItem {
             name: "item1".to_string(),
-            path: "#Content > div:nth-child(5) > strong".to_string(),
+            path: vec!["#Content > div:nth-child(5) > strong".to_string()],
             primary: true,
             item_type: FetchItemType::Text,
             related: vec![translations.clone()],
+            ..Default::default()
         };
 
         let config1 = FetcherConfig {
             client_type: ClientType::Simple,
             items: vec![translations.clone(), item1.clone()],
             url: "https://www.lipsum.com/".to_string(),
+            cassette: Some(CassetteConfig::replay("test/cassettes")),
+            ..Default::default()
         };
 
-        let mut fetched =
-            FetchDaemon::fetch_data(vec![Box::new(SimpleFetcher { config: config1 })]).await;
-        fetched.sort();
+        let fetched = items(
+            FetchDaemon::fetch_data(vec![Box::new(SimpleFetcher { config: config1 })])
+                .await
+                .0,
+        );
 
         let correct = vec![FoundItem {
             fetch_item: item1,
@@ -242,11 +669,177 @@ mod tests {
                 fetch_item: translations,
                 content: Arr(vec!["boxed".to_string()]),
                 related: vec![],
+                selector: 0,
             })],
+            selector: 0,
         }];
         let mut correct = vec![correct];
         correct.sort();
 
         assert_eq!(fetched, correct)
     }
+
+    #[test]
+    fn test_fallback_warnings() {
+        let item = FetchItem {
+            name: "price".to_string(),
+            path: vec!["div._3NaXx".to_string(), "span.price".to_string()],
+            ..Default::default()
+        };
+        let found = |selector| FoundItem {
+            fetch_item: item.clone(),
+            content: Str("990".to_string()),
+            related: vec![],
+            selector,
+        };
+
+        let result = |id: &str, selector| AimResult {
+            aim: AimInfo {
+                id: id.to_string(),
+                ..Default::default()
+            },
+            items: vec![found(selector)],
+        };
+
+        assert!(FetchDaemon::fallback_warnings(&[result("kettle", 0)]).is_empty());
+        let warnings = FetchDaemon::fallback_warnings(&[result("kettle", 0), result("pods", 1)]);
+        assert_eq!(
+            warnings.into_iter().collect::<Vec<_>>(),
+            vec![(
+                (
+                    "pods".to_string(),
+                    "price".to_string(),
+                    "span.price".to_string()
+                ),
+                "Primary selector div._3NaXx of price in aim pods stopped matching, fallback span.price is used"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let kettle = AimInfo {
+            id: "kettle".to_string(),
+            name: Some("Kettle".to_string()),
+            tags: vec!["prices".to_string()],
+            ..Default::default()
+        };
+        let price = |value: &str| AimResult {
+            aim: kettle.clone(),
+            items: vec![FoundItem {
+                fetch_item: FetchItem {
+                    name: "price".to_string(),
+                    ..Default::default()
+                },
+                content: Str(value.to_string()),
+                related: vec![],
+                selector: 0,
+            }],
+        };
+        let (runs, mut run_requests) = mpsc::channel(1);
+        let handle = DaemonHandle::new(Default::default(), runs, "aims");
+        let ids = vec!["kettle".to_string(), "pods".to_string()];
+        {
+            let mut state = handle.state.lock().unwrap();
+            state.aims = vec![
+                kettle.clone(),
+                AimInfo {
+                    id: "pods".to_string(),
+                    ..Default::default()
+                },
+            ];
+            let errors = BTreeMap::from([("pods".to_string(), "Select failed".to_string())]);
+            state.record(&ids, &[price("990")], errors.clone());
+            state.record(&ids, &[price("990")], errors.clone());
+            state.record(&ids, &[price("1050")], errors);
+        }
+        let handler: Arc<dyn CommandHandler> = Arc::new(handle);
+        let handle = |command| handler.handle("42", command);
+
+        assert_eq!(
+            handle(Command::List).await,
+            "kettle - Kettle [prices]\npods"
+        );
+        let status = handle(Command::Status).await;
+        assert!(status.starts_with("Kettle: price=1050 at "));
+        assert!(status.contains("\npods: failed at "));
+        assert!(status.ends_with(": Select failed"));
+
+        let history = handle(Command::History("kettle".to_string(), "price".to_string())).await;
+        let values: Vec<_> = history
+            .lines()
+            .map(|line| line.rsplit(": ").next().unwrap())
+            .collect();
+        assert_eq!(values, vec!["990", "1050"]);
+
+        assert_eq!(
+            handle(Command::Pause("kettle".to_string())).await,
+            "kettle is paused"
+        );
+        assert!(handle(Command::List).await.contains("(paused)"));
+        assert_eq!(
+            handle(Command::Resume("kettle".to_string())).await,
+            "kettle is resumed"
+        );
+        assert_eq!(
+            handle(Command::Run("toaster".to_string())).await,
+            "There is no aim toaster"
+        );
+        assert_eq!(
+            handle(Command::Run("pods".to_string())).await,
+            "Fetching pods"
+        );
+        // the daemon hasn't taken the first one yet
+        assert_eq!(
+            handle(Command::Run("kettle".to_string())).await,
+            "Too many aims are waiting to be fetched, try kettle later"
+        );
+        assert_eq!(run_requests.recv().await.unwrap(), "pods");
+    }
+
+    #[tokio::test]
+    async fn test_add_aim() {
+        let page = || {
+            response(
+                "200 OK",
+                &["content-type: text/html"],
+                r#"<p>Kettle</p><p><b class="price">990 ₽</b></p>"#,
+            )
+        };
+        let (url, server) = serve(vec![page(), page()]).await;
+        let dir = "test/added_aims";
+        fs::create_dir_all(dir).unwrap();
+        let (runs, mut run_requests) = mpsc::channel(8);
+        let handle = DaemonHandle::new(Default::default(), runs, dir);
+
+        let asked = handle.handle("42", Command::Add(url, None)).await;
+        let missing = handle.handle("42", Command::Text("1050".to_string())).await;
+        let probed = handle.handle("42", Command::Text("990".to_string())).await;
+        let other_chat = handle.handle("7", Command::Text("990".to_string())).await;
+        let confirmed = handle
+            .handle("42", Command::Confirm(Some("kettle".to_string())))
+            .await;
+        let again = handle.handle("42", Command::Confirm(None)).await;
+        server.await.unwrap();
+        let written = parse_aim_file(Path::new("test/added_aims/kettle.yaml"));
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            asked,
+            "Send a CSS selector of the value or a piece of its text"
+        );
+        assert!(missing.starts_with("Couldn't find it"));
+        assert!(probed.starts_with("body > p:nth-child(2) > b gives 990 ₽\n"));
+        assert_eq!(other_chat, "");
+        assert_eq!(confirmed, "Aim kettle is added and being fetched");
+        assert_eq!(run_requests.recv().await.unwrap(), "kettle");
+        assert!(again.starts_with("There is no aim to confirm"));
+        let written = written.unwrap();
+        assert_eq!(written[0].config().id, "kettle");
+        assert_eq!(
+            written[0].config().items[0].path,
+            vec!["body > p:nth-child(2) > b".to_string()]
+        );
+    }
 }
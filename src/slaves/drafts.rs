@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+
+use super::{
+    fetchers::{
+        FetchItem, FetchItemType, FetcherConfig, FoundItemContent, HtmlFetchable, SimpleFetcher,
+    },
+    xpath::XmlDocument,
+};
+
+/// Aim put together in a chat, written into the aims directory once confirmed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AimDraft {
+    pub url: String,
+    pub selector: String,
+    /// What the selector gives on the page now
+    pub value: String,
+}
+
+/// Just what is needed in a new aim file, the rest is left to the defaults.
+#[derive(Serialize)]
+struct AimFile<'a> {
+    id: &'a str,
+    url: &'a str,
+    items: Vec<ItemFile<'a>>,
+}
+
+#[derive(Serialize)]
+struct ItemFile<'a> {
+    name: &'a str,
+    path: &'a str,
+    primary: bool,
+    item_type: &'a str,
+    related: Vec<()>,
+}
+
+/// Whether the element has the text itself rather than only one of its children.
+fn holds_text(element: ElementRef, sample: &str) -> bool {
+    element.text().collect::<String>().contains(sample)
+        && !element
+            .children()
+            .filter_map(ElementRef::wrap)
+            .any(|child| child.text().collect::<String>().contains(sample))
+}
+
+/// Selector in the `body > div > p:nth-child(3)` form, starting from the closest `id`.
+fn selector_of(element: ElementRef) -> String {
+    let mut parts = vec![];
+    let mut current = Some(element);
+    while let Some(element) = current {
+        let value = element.value();
+        if let Some(id) = value
+            .id()
+            .filter(|id| Selector::parse(&format!("#{}", id)).is_ok())
+        {
+            parts.push(format!("#{}", id));
+            break;
+        }
+        if value.name() == "body" || value.name() == "html" {
+            parts.push(value.name().to_string());
+            break;
+        }
+        let parent = element.parent().and_then(ElementRef::wrap);
+        let siblings: Vec<_> = parent
+            .iter()
+            .flat_map(|parent| parent.children().filter_map(ElementRef::wrap))
+            .collect();
+        match siblings
+            .iter()
+            .position(|sibling| sibling.id() == element.id())
+        {
+            Some(index) if siblings.len() > 1 => {
+                parts.push(format!("{}:nth-child({})", value.name(), index + 1))
+            }
+            _ => parts.push(value.name().to_string()),
+        }
+        current = parent;
+    }
+    parts.reverse();
+    parts.join(" > ")
+}
+
+/// Selector of the innermost element having the sample text.
+fn find_text(tree: &Html, sample: &str) -> Result<String> {
+    let all = Selector::parse("body *").unwrap();
+    tree.select(&all)
+        .find(|element| holds_text(*element, sample))
+        .map(selector_of)
+        .ok_or_else(|| anyhow!("There is no {:?} on the page", sample))
+}
+
+impl AimDraft {
+    fn item(selector: &str) -> FetchItem {
+        FetchItem {
+            name: "value".to_string(),
+            path: vec![selector.to_string()],
+            primary: true,
+            item_type: FetchItemType::Text,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the page and finds the value by a CSS selector or, when nothing matches it, by
+    /// a piece of the text.
+    pub async fn probe(url: &str, query: &str) -> Result<AimDraft> {
+        Url::parse(url)?;
+        let fetcher = SimpleFetcher {
+            config: FetcherConfig {
+                url: url.to_string(),
+                ..Default::default()
+            },
+        };
+        let tree = fetcher.retrieve().await?;
+        let selector = match FetchItem::select(query, &tree) {
+            Ok(_) => query.to_string(),
+            Err(_) => find_text(&tree, query)?,
+        };
+        // the way the daemon will read it
+        let value = match Self::item(&selector)
+            .find(&tree, &XmlDocument::default())?
+            .1
+        {
+            FoundItemContent::Str(value) => value,
+            content => content.text(),
+        };
+        Ok(AimDraft {
+            url: url.to_string(),
+            selector,
+            value: value.trim().to_string(),
+        })
+    }
+
+    /// Id made of the host and the last path segment, like `shop-example-1001`.
+    pub fn default_id(&self) -> String {
+        let url = Url::parse(&self.url).ok();
+        let host = url.as_ref().and_then(Url::host_str).unwrap_or("aim");
+        let last = url
+            .as_ref()
+            .and_then(|url| url.path_segments()?.rfind(|part| !part.is_empty()))
+            .unwrap_or_default()
+            .to_string();
+        format!("{}-{}", host, last)
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_lowercase(),
+                false => '-',
+            })
+            .collect::<String>()
+            .trim_matches('-')
+            .to_string()
+    }
+
+    /// Writes `<id>.yaml` into the aims directory, an existing file is never replaced.
+    pub fn write(&self, dir: &Path, id: &str) -> Result<PathBuf> {
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_".contains(c))
+        {
+            return Err(anyhow!("Aim id may only have letters, digits, - and _"));
+        }
+        let path = dir.join(format!("{}.yaml", id));
+        if path.exists() {
+            return Err(anyhow!("There is an aim {} already", id));
+        }
+        let item = Self::item(&self.selector);
+        let aim = AimFile {
+            id,
+            url: &self.url,
+            items: vec![ItemFile {
+                name: &item.name,
+                path: &item.path[0],
+                primary: item.primary,
+                item_type: "Text",
+                related: vec![],
+            }],
+        };
+        std::fs::write(&path, serde_yaml::to_string(&aim)?)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use crate::slaves::{
+        clients::http::tests::{response, serve},
+        config_parser::parse_aim_file,
+    };
+
+    use super::AimDraft;
+
+    const PAGE: &str = r#"<html><body><div class="card"><h1>Kettle</h1>
+        <p>In stock</p><p><span class="price">990 ₽</span></p></div></body></html>"#;
+
+    #[tokio::test]
+    async fn test_probe() {
+        let page = || response("200 OK", &["content-type: text/html"], PAGE);
+        let (url, server) = serve(vec![page(), page(), page()]).await;
+
+        let by_selector = AimDraft::probe(&url, "span.price").await.unwrap();
+        let by_text = AimDraft::probe(&url, "990").await.unwrap();
+        let missing = AimDraft::probe(&url, "1050").await;
+        server.await.unwrap();
+
+        assert_eq!(by_selector.value, "990 ₽");
+        assert_eq!(by_text.selector, "body > div > p:nth-child(3) > span");
+        assert_eq!(by_text.value, "990 ₽");
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let draft = AimDraft {
+            url: "https://shop.example/product/1001".to_string(),
+            selector: "span.price".to_string(),
+            value: "990 ₽".to_string(),
+        };
+        let dir = Path::new("test/drafts");
+        fs::create_dir_all(dir).unwrap();
+        let id = draft.default_id();
+        let written = draft.write(dir, &id);
+        let again = draft.write(dir, &id);
+        let fetchers = parse_aim_file(&dir.join("shop-example-1001.yaml"));
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(id, "shop-example-1001");
+        written.unwrap();
+        assert!(again.is_err());
+        assert!(draft.write(dir, "../escape").is_err());
+        let fetchers = fetchers.unwrap();
+        let config = fetchers[0].config();
+        assert_eq!(config.id, "shop-example-1001");
+        assert_eq!(config.url, "https://shop.example/product/1001");
+        assert_eq!(config.items[0].path, vec!["span.price".to_string()]);
+    }
+}
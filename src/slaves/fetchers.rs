@@ -5,14 +5,36 @@ use std::{
 
 use anyhow::{anyhow, Result};
 use scraper::{ElementRef, Html, Selector};
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use async_trait::async_trait;
+use reqwest::Client;
 
-#[derive(Debug, Deserialize, Clone, PartialEq, PartialOrd, Ord, Eq)]
+use super::{
+    clients::{
+        cache::{CacheConfig, NotModified},
+        cassette::CassetteConfig,
+        headers::HeadersConfig,
+        http::{AimClient, RetryConfig},
+        proxy::ProxyConfig,
+    },
+    crawler::{self, FollowConfig},
+    pagination::{self, PaginationConfig},
+    structured,
+    xpath::XmlDocument,
+};
+
+#[derive(Debug, Deserialize, Clone, PartialEq, PartialOrd, Ord, Eq, Default)]
 pub enum FetchItemType {
     Class,
+    #[default]
     Text,
+    /// `application/ld+json` value, `path` is a schema.org type and property path like `Product/offers/price`
+    JsonLd,
+    /// `itemprop` value, `path` is like `Product/offers/price`
+    Microdata,
+    /// `content` of a meta tag, `path` is its `property` or `name` like `og:title`
+    Meta,
 }
 use FetchItemType::*;
 
@@ -25,10 +47,55 @@ pub enum FoundItemContent {
 
 use FoundItemContent::*;
 
-#[derive(Debug, Deserialize, Clone, PartialEq, PartialOrd, Ord, Eq)]
+impl FoundItemContent {
+    pub fn values(&self) -> Vec<String> {
+        match self {
+            Str(value) => vec![value.clone()],
+            Arr(values) => values.clone(),
+        }
+    }
+
+    /// Values joined for people to read.
+    pub fn text(&self) -> String {
+        self.values().join(", ")
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, PartialOrd, Ord, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectorKind {
+    #[default]
+    Css,
+    Xpath,
+}
+
+/// `path: selector` or a list of selectors tried in order
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq, PartialOrd, Ord, Eq, Default)]
 pub struct FetchItem {
     pub name: String,
-    pub path: String,
+    /// Selectors tried in order, the ones after the first are fallbacks
+    #[serde(deserialize_with = "one_or_many")]
+    pub path: Vec<String>,
+    /// How `path` is read, CSS selectors unless set to `xpath`
+    #[serde(default)]
+    pub selector_kind: SelectorKind,
     pub primary: bool,
     pub item_type: FetchItemType,
     pub related: Vec<Self>,
@@ -44,17 +111,40 @@ impl FetchItem {
                 .into_iter()
                 .map(|x| x.to_string())
                 .collect()),
-            Text => Str(data.inner_html()),
+            Text | JsonLd | Microdata | Meta => Str(data.inner_html()),
         }
     }
 
-    pub fn select<'a>(&'a self, tree: &'a Html) -> Result<ElementRef> {
+    pub fn select<'a>(path: &str, tree: &'a Html) -> Result<ElementRef<'a>> {
         let selector =
-            Selector::parse(&self.path).map_err(|x| anyhow!("Selector parsing errored {:?}", x))?;
+            Selector::parse(path).map_err(|x| anyhow!("Selector parsing errored {:?}", x))?;
         tree.select(&selector)
             .next()
             .ok_or_else(|| anyhow!("Select failed"))
     }
+
+    /// Tries the paths in order, giving the index of the first one that matched.
+    pub fn first_match<T>(&self, mut find: impl FnMut(&str) -> Result<T>) -> Result<(usize, T)> {
+        let mut last_err = anyhow!("{} has no path", self.name);
+        for (index, path) in self.path.iter().enumerate() {
+            match find(path) {
+                Ok(found) => return Ok((index, found)),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// `xml` is the page as `tree` for XPath lookups, made once for all the items of the page.
+    pub fn find(&self, tree: &Html, xml: &XmlDocument) -> Result<(usize, FoundItemContent)> {
+        self.first_match(|path| match (&self.item_type, &self.selector_kind) {
+            (JsonLd, _) => Ok(Str(structured::json_ld(tree, path)?)),
+            (Microdata, _) => Ok(Str(structured::microdata(tree, path)?)),
+            (Meta, _) => Ok(Str(structured::meta(tree, path)?)),
+            (_, SelectorKind::Css) => Ok(self.seek(Self::select(path, tree)?)),
+            (_, SelectorKind::Xpath) => xml.find(tree, path, &self.item_type),
+        })
+    }
 }
 
 impl Serialize for FetchItem {
@@ -72,64 +162,223 @@ pub struct FoundItem {
     pub fetch_item: FetchItem,
     pub content: FoundItemContent,
     pub related: Vec<Option<FoundItem>>,
+    /// Index of the `path` entry that matched, non-zero when a fallback was used
+    #[serde(skip_serializing_if = "FoundItem::is_primary_selector")]
+    pub selector: usize,
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+impl FoundItem {
+    fn is_primary_selector(selector: &usize) -> bool {
+        *selector == 0
+    }
+
+    pub fn matched_path(&self) -> &str {
+        &self.fetch_item.path[self.selector]
+    }
+
+    /// Items found only by a fallback selector, related ones included.
+    pub fn fallbacks(&self) -> Vec<&FoundItem> {
+        let mut found = vec![];
+        if self.selector > 0 {
+            found.push(self);
+        }
+        for item in self.related.iter().flatten() {
+            found.extend(item.fallbacks());
+        }
+        found
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Default)]
 pub enum ClientType {
+    #[default]
     Simple,
     Yandex,
+    /// Saved pages on disk (`file://` urls, glob patterns allowed) or stdin (`-`)
+    File,
+    /// JSON endpoints, item paths are JSONPath expressions
+    Json,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct CookiesConfig {
+    #[serde(default = "CookiesConfig::default_dir")]
+    pub dir: String,
+    /// Browser exports (Netscape `cookies.txt` or Firefox `cookies.sqlite`) loaded into a new
+    /// jar, remove the saved jar to import them again
+    #[serde(default)]
+    pub import: Vec<String>,
 }
 
-impl Default for ClientType {
+impl CookiesConfig {
+    fn default_dir() -> String {
+        "cookies".to_string()
+    }
+}
+
+impl Default for CookiesConfig {
     fn default() -> Self {
-        Self::Simple
+        CookiesConfig {
+            dir: Self::default_dir(),
+            import: vec![],
+        }
     }
 }
 
-#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord, Default)]
 pub struct FetcherConfig {
+    /// Unique among the aims, the file stem unless set
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Handlebars template of the messages about the aim, see `messages`
+    #[serde(default)]
+    pub message: Option<String>,
     #[serde(default)]
     pub client_type: ClientType,
     pub items: Vec<FetchItem>,
     pub url: String,
+    #[serde(default)]
+    pub cookies: CookiesConfig,
+    /// Overrides the global proxy pool from `config/proxies.yaml`
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Browser header profiles the requests of the aim are sent with
+    #[serde(default)]
+    pub headers: Option<HeadersConfig>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// On-disk response cache, conditional requests are sent without it too
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+    /// Forces the page encoding (e.g. `windows-1251`) instead of detecting it
+    #[serde(default)]
+    pub encoding: Option<String>,
+    /// Records responses of the aim or replays them instead of going online
+    #[serde(default)]
+    pub cassette: Option<CassetteConfig>,
+    /// Links to follow from the page, each linked page gives its own result set. Simple aims only
+    #[serde(default)]
+    pub follow: Option<FollowConfig>,
+    /// Further pages of a listing, their results are merged into the result of the aim. Simple
+    /// aims only
+    #[serde(default)]
+    pub pagination: Option<PaginationConfig>,
+}
+
+impl FetcherConfig {
+    pub fn info(&self) -> AimInfo {
+        AimInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            tags: self.tags.clone(),
+            description: self.description.clone(),
+            message: self.message.clone(),
+        }
+    }
+}
+
+/// Aim metadata carried along with its results.
+#[derive(Clone, Debug, Serialize, PartialEq, PartialOrd, Eq, Ord, Default)]
+pub struct AimInfo {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing)]
+    pub message: Option<String>,
+}
+
+impl AimInfo {
+    /// Name for people, the id when the aim has none.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.id)
+    }
+}
+
+/// Items found on one page of an aim.
+#[derive(Clone, Debug, Serialize, PartialEq, PartialOrd, Eq, Ord)]
+pub struct AimResult {
+    pub aim: AimInfo,
+    pub items: Vec<FoundItem>,
 }
 
 pub type FetchResults = Vec<Option<FoundItem>>;
 
 #[async_trait]
 pub trait Fetchable: Debug + Send + 'static {
-    async fn retrieve(&self) -> Result<Html>;
+    // only the tests downcast so far
+    #[allow(dead_code)]
     fn as_any(&self) -> &dyn Any;
     fn config(&self) -> &FetcherConfig;
+    async fn fetch(&self) -> Result<FetchResults>;
 
-    async fn fetch(&self) -> Result<FetchResults> {
-        let tree = self.retrieve().await?;
+    /// Result sets of every page behind the aim, a single one unless the fetcher says otherwise.
+    async fn fetch_all(&self) -> Result<Vec<FetchResults>> {
+        Ok(vec![self.fetch().await?])
+    }
+}
+
+/// Fetchers of HTML pages, the items are looked up in the tree of the page.
+#[async_trait]
+pub trait HtmlFetchable: Fetchable {
+    async fn retrieve(&self) -> Result<Html>;
+
+    /// Items of the page `retrieve` gives.
+    async fn fetch_page(&self) -> Result<FetchResults> {
+        let tree = match self.retrieve().await {
+            // nothing changed since the previous fetch, so there is nothing new to report
+            Err(err) if err.is::<NotModified>() => return Ok(vec![]),
+            tree => tree?,
+        };
+        Ok(self.extract(&tree))
+    }
+
+    fn extract(&self, tree: &Html) -> FetchResults {
+        self.extract_items(&self.config().items, tree)
+    }
+
+    fn extract_items(&self, items: &[FetchItem], tree: &Html) -> FetchResults {
+        let xml = XmlDocument::default();
         let mut fetched = vec![];
-        let config = self.config();
-        let primary_items: Vec<_> = config.items.iter().filter(|&item| item.primary).collect();
+        let primary_items: Vec<_> = items.iter().filter(|&item| item.primary).collect();
         for primary_item in primary_items {
-            let result = if let Some(mut found_item) = self.process_single_item(primary_item, &tree)
-            {
-                let mut related_items = vec![];
-                for item in primary_item.related.iter() {
-                    related_items.push(self.process_single_item(item, &tree))
-                }
-                found_item.related = related_items;
-                Some(found_item)
-            } else {
-                None
-            };
+            let result =
+                if let Some(mut found_item) = self.process_single_item(primary_item, tree, &xml) {
+                    let mut related_items = vec![];
+                    for item in primary_item.related.iter() {
+                        related_items.push(self.process_single_item(item, tree, &xml))
+                    }
+                    found_item.related = related_items;
+                    Some(found_item)
+                } else {
+                    None
+                };
             fetched.push(result)
         }
-        Ok(fetched)
+        fetched
     }
 
-    fn process_single_item(&self, item: &FetchItem, tree: &Html) -> Option<FoundItem> {
-        if let Ok(data) = item.select(&tree) {
+    fn process_single_item(
+        &self,
+        item: &FetchItem,
+        tree: &Html,
+        xml: &XmlDocument,
+    ) -> Option<FoundItem> {
+        if let Ok((selector, content)) = item.find(tree, xml) {
             Some(FoundItem {
                 fetch_item: item.clone(),
-                content: item.seek(data),
+                content,
                 related: vec![],
+                selector,
             })
         } else {
             None
@@ -148,19 +397,50 @@ impl Fetchable for SimpleFetcher {
         self
     }
 
-    async fn retrieve(&self) -> Result<Html> {
-        let resp_text = reqwest::get(&self.config.url).await?.text().await?;
-        Ok(Html::parse_document(&resp_text[..]))
-    }
-
     fn config(&self) -> &FetcherConfig {
         &self.config
     }
+
+    async fn fetch(&self) -> Result<FetchResults> {
+        let client = AimClient::new(&self.config, Client::builder(), None)?;
+        self.fetch_pages(&client).await
+    }
+
+    async fn fetch_all(&self) -> Result<Vec<FetchResults>> {
+        let client = AimClient::new(&self.config, Client::builder(), None)?;
+        match &self.config.follow {
+            Some(follow) => crawler::crawl(self, follow, &client).await,
+            None => Ok(vec![self.fetch_pages(&client).await?]),
+        }
+    }
+}
+
+impl SimpleFetcher {
+    /// Items of the aim page or, when paginated, of all its pages as one result.
+    async fn fetch_pages(&self, client: &AimClient) -> Result<FetchResults> {
+        let paginated = self.config.pagination.is_some();
+        let pages = pagination::read_pages(client, &self.config, |_, tree| {
+            let found = self.extract(tree);
+            // a page without any of the items ends the list
+            Some(found).filter(|found| !paginated || found.iter().any(Option::is_some))
+        })
+        .await?;
+        Ok(pages.into_iter().flatten().collect())
+    }
+}
+
+#[async_trait]
+impl HtmlFetchable for SimpleFetcher {
+    async fn retrieve(&self) -> Result<Html> {
+        let client = AimClient::new(&self.config, Client::builder(), None)?;
+        let resp_text = client.get_text(&self.config.url).await?;
+        Ok(Html::parse_document(&resp_text[..]))
+    }
 }
 
 impl Display for SimpleFetcher {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Fetcher: url={}", self.config.url)
+        write!(f, "Fetcher: id={} url={}", self.config.id, self.config.url)
     }
 }
 
@@ -168,32 +448,50 @@ impl Display for SimpleFetcher {
 mod tests {
     use std::collections::HashMap;
 
+    use reqwest::Client;
     use scraper::{Html, Selector};
 
-    use crate::slaves::fetchers::{
-        ClientType, FetchItem, FetchItemType, Fetchable, FetcherConfig, FoundItemContent,
-        SimpleFetcher,
+    use crate::slaves::{
+        clients::{
+            cassette::CassetteConfig,
+            http::{
+                tests::{response, serve},
+                AimClient,
+            },
+        },
+        fetchers::{
+            ClientType, FetchItem, FetchItemType, Fetchable, FetcherConfig, FoundItemContent,
+            SimpleFetcher,
+        },
     };
 
     #[tokio::test]
     async fn reqwest_works() {
-        let resp = reqwest::get("https://httpbin.org/ip")
+        let (url, server) = serve(vec![response(
+            "200 OK",
+            &["content-type: application/json"],
+            r#"{"origin": "127.0.0.1"}"#,
+        )])
+        .await;
+        let resp = reqwest::get(&url)
             .await
             .unwrap()
             .json::<HashMap<String, String>>()
             .await
             .unwrap();
-        assert!(resp.contains_key("origin"))
+        assert!(resp.contains_key("origin"));
+        server.await.unwrap();
     }
 
     #[tokio::test]
     async fn scraper_works() {
-        let resp_text = reqwest::get("http://example.com/")
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
+        let config = FetcherConfig {
+            url: "http://example.com/".to_string(),
+            cassette: Some(CassetteConfig::replay("test/cassettes")),
+            ..Default::default()
+        };
+        let client = AimClient::new(&config, Client::builder(), None).unwrap();
+        let resp_text = client.get_text(&config.url).await.unwrap();
         let tree = Html::parse_document(&resp_text[..]);
         let selector = Selector::parse("body > div > p:nth-child(3) > a").unwrap();
         let selected_text = tree.select(&selector).take(1).collect::<Vec<_>>()[0].inner_html();
@@ -204,10 +502,11 @@ mod tests {
     async fn test_base_fetcher() {
         let item1 = FetchItem {
             name: "item1".to_string(),
-            path: "body > div > p:nth-child(3) > a".to_string(),
+            path: vec!["body > div > p:nth-child(3) > a".to_string()],
             primary: true,
             item_type: FetchItemType::Text,
             related: vec![],
+            ..Default::default()
         };
 
         let fetcher = SimpleFetcher {
@@ -215,6 +514,8 @@ mod tests {
                 client_type: ClientType::Simple,
                 items: vec![item1],
                 url: "http://example.com/".to_string(),
+                cassette: Some(CassetteConfig::replay("test/cassettes")),
+                ..Default::default()
             },
         };
 
@@ -225,4 +526,30 @@ mod tests {
             FoundItemContent::Str("More information...".to_string())
         );
     }
+
+    #[tokio::test]
+    async fn test_fallback_selectors() {
+        let item: FetchItem = serde_yaml::from_str(
+            "name: link\npath: [div._3NaXx, 'p > a']\nprimary: true\nitem_type: Text\nrelated: []",
+        )
+        .unwrap();
+        let fetcher = SimpleFetcher {
+            config: FetcherConfig {
+                items: vec![item],
+                url: "http://example.com/".to_string(),
+                cassette: Some(CassetteConfig::replay("test/cassettes")),
+                ..Default::default()
+            },
+        };
+
+        let fetched = fetcher.fetch().await.unwrap();
+        let found = fetched[0].as_ref().unwrap();
+        assert_eq!(
+            found.content,
+            FoundItemContent::Str("More information...".to_string())
+        );
+        assert_eq!(found.selector, 1);
+        assert_eq!(found.matched_path(), "p > a");
+        assert_eq!(found.fallbacks(), vec![found]);
+    }
 }
@@ -0,0 +1,257 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use handlebars::Handlebars;
+use serde::Serialize;
+
+use super::{
+    fetchers::{AimInfo, AimResult, FoundItem},
+    serializer::{serialize_all, SerType},
+};
+
+/// What a message template can use, e.g. `{{label}}: {{item.price.value}} ({{item.price.diff}})`
+/// or `{{#each items}}{{name}}={{value}}{{/each}}`.
+#[derive(Serialize)]
+struct MessageContext<'a> {
+    aim: &'a AimInfo,
+    /// Aim name, the id when it has none
+    label: &'a str,
+    /// Items by name
+    item: BTreeMap<String, ItemContext>,
+    /// Items in the order of the aim
+    items: Vec<ItemContext>,
+    /// Whether any item differs from the previous message
+    changed: bool,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct ItemContext {
+    name: String,
+    value: String,
+    values: Vec<String>,
+    /// Value in the previous message about the aim, none in the first one
+    previous: Option<String>,
+    changed: bool,
+    /// Signed difference like `+60` when both values are numbers
+    diff: Option<String>,
+    /// Values of the related items by name
+    related: BTreeMap<String, String>,
+}
+
+/// Number in texts like `19 990 ₽` or `$1,299.50`, commas are taken for thousands separators.
+fn number(text: &str) -> Option<f64> {
+    let digits: String = text
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+        .collect();
+    digits.parse().ok()
+}
+
+fn diff(previous: &str, value: &str) -> Option<String> {
+    let diff = number(value)? - number(previous)?;
+    let diff = match diff.fract() == 0.0 {
+        true => format!("{}", diff as i64),
+        false => format!("{:.2}", diff),
+    };
+    Some(match diff.starts_with('-') {
+        true => diff,
+        false => format!("+{}", diff),
+    })
+}
+
+/// Last values of every item, keyed by aim id, index of the result within the aim and item name.
+type Previous = BTreeMap<(String, usize, String), String>;
+
+/// Renders results with message templates, an aim's own one before the sink's, and remembers the
+/// values sent to fill `previous` and `diff` next time.
+#[derive(Clone, Default)]
+pub struct Messages {
+    template: Option<String>,
+    /// Escapes values for the markup of the sink, templates themselves may use the markup
+    escape: Option<fn(&str) -> String>,
+    previous: Arc<Mutex<Previous>>,
+}
+
+impl Messages {
+    pub fn with_template(self, template: String) -> Self {
+        Messages {
+            template: Some(template),
+            ..self
+        }
+    }
+
+    pub fn with_escape(self, escape: fn(&str) -> String) -> Self {
+        Messages {
+            escape: Some(escape),
+            ..self
+        }
+    }
+
+    fn item_context(item: &FoundItem, previous: Option<&String>) -> ItemContext {
+        let values = item.content.values();
+        let value = item.content.text();
+        ItemContext {
+            name: item.fetch_item.name.clone(),
+            changed: previous.is_some_and(|previous| *previous != value),
+            diff: previous.and_then(|previous| diff(previous, &value)),
+            previous: previous.cloned(),
+            related: item
+                .related
+                .iter()
+                .flatten()
+                .map(|related| (related.fetch_item.name.clone(), related.content.text()))
+                .collect(),
+            value,
+            values,
+        }
+    }
+
+    fn render(
+        &self,
+        template: &str,
+        result: &AimResult,
+        items: Vec<ItemContext>,
+    ) -> Result<String> {
+        let context = MessageContext {
+            aim: &result.aim,
+            label: result.aim.label(),
+            item: items
+                .iter()
+                .map(|item| (item.name.clone(), item.clone()))
+                .collect(),
+            changed: items.iter().any(|item| item.changed),
+            items,
+        };
+        let mut registry = Handlebars::new();
+        // messages are plain text unless the sink says otherwise, never html
+        match self.escape {
+            Some(escape) => registry.register_escape_fn(escape),
+            None => registry.register_escape_fn(handlebars::no_escape),
+        }
+        Ok(registry.render_template(template, &context)?)
+    }
+
+    /// One message per result, results without a template are serialized as plain text.
+    pub fn render_all(&self, results: &[AimResult]) -> String {
+        let mut previous = self.previous.lock().unwrap();
+        let mut seen = BTreeMap::new();
+        results
+            .iter()
+            .map(|result| {
+                let index = seen.entry(&result.aim.id).or_insert(0);
+                let key = |name: &str| (result.aim.id.clone(), *index, name.to_string());
+                let items: Vec<_> = result
+                    .items
+                    .iter()
+                    .map(|item| Self::item_context(item, previous.get(&key(&item.fetch_item.name))))
+                    .collect();
+                for item in &items {
+                    previous.insert(key(&item.name), item.value.clone());
+                }
+                *index += 1;
+                let plain = || {
+                    let plain = serialize_all(vec![result.clone()], SerType::Plain);
+                    self.escape
+                        .map_or_else(|| plain.clone(), |escape| escape(&plain))
+                };
+                match result.aim.message.as_ref().or(self.template.as_ref()) {
+                    Some(template) => self.render(template, result, items).unwrap_or_else(|err| {
+                        eprintln!(
+                            "Message template of aim {} failed: {:?}",
+                            result.aim.id, err
+                        );
+                        plain()
+                    }),
+                    None => plain(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slaves::fetchers::{AimInfo, AimResult, FetchItem, FoundItem, FoundItemContent::*};
+
+    use crate::slaves::notifier::TgFormat;
+
+    use super::{diff, Messages};
+
+    fn result(price: &str, message: Option<&str>) -> AimResult {
+        let item = |name: &str, value: &str| FoundItem {
+            fetch_item: FetchItem {
+                name: name.to_string(),
+                ..Default::default()
+            },
+            content: Str(value.to_string()),
+            related: vec![],
+            selector: 0,
+        };
+        AimResult {
+            aim: AimInfo {
+                id: "kettle".to_string(),
+                name: Some("Kettle".to_string()),
+                message: message.map(str::to_string),
+                ..Default::default()
+            },
+            items: vec![FoundItem {
+                related: vec![Some(item("shop", "M.Video"))],
+                ..item("price", price)
+            }],
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("19 990 ₽", "20 050 ₽").unwrap(), "+60");
+        assert_eq!(diff("$1,299.50", "$1,289.25").unwrap(), "-10.25");
+        assert_eq!(diff("990", "990").unwrap(), "+0");
+        assert!(diff("in stock", "sold out").is_none());
+    }
+
+    #[test]
+    fn test_render_all() {
+        let messages = Messages::default().with_template(
+            "{{label}}: {{item.price.value}} at {{item.price.related.shop}}\
+             {{#if changed}}, was {{item.price.previous}} ({{item.price.diff}}){{/if}}"
+                .to_string(),
+        );
+
+        assert_eq!(
+            messages.render_all(&[result("990 ₽", None)]),
+            "Kettle: 990 ₽ at M.Video"
+        );
+        assert_eq!(
+            messages.render_all(&[result("1 050 ₽", None)]),
+            "Kettle: 1 050 ₽ at M.Video, was 990 ₽ (+60)"
+        );
+        assert_eq!(
+            messages.render_all(&[result(
+                "1 050 ₽",
+                Some("{{#each items}}{{name}} <{{value}}>{{/each}}")
+            )]),
+            "price <1 050 ₽>"
+        );
+        assert_eq!(
+            Messages::default().render_all(&[result("990 ₽", None)]),
+            "[Kettle] price=990 ₽: shop=M.Video"
+        );
+
+        let markdown = Messages::default().with_escape(TgFormat::MarkdownV2.escape());
+        assert_eq!(
+            markdown
+                .clone()
+                .with_template("*{{label}}*: {{item.price.related.shop}}".to_string())
+                .render_all(&[result("990", None)]),
+            r"*Kettle*: M\.Video"
+        );
+        assert_eq!(
+            markdown.render_all(&[result("990", None)]),
+            r"\[Kettle\] price\=990: shop\=M\.Video"
+        );
+    }
+}
@@ -1,8 +1,16 @@
+pub mod clients;
+pub mod collector;
+pub mod commands;
+pub mod config_parser;
+pub mod crawler;
 pub mod daemon;
+pub mod drafts;
 pub mod fetchers;
-pub mod config_parser;
-pub mod serializer;
-pub mod saver;
-pub mod clients;
+pub mod messages;
 pub mod notifier;
-pub mod collector;
\ No newline at end of file
+pub mod pagination;
+pub mod saver;
+pub mod serializer;
+pub mod structured;
+pub mod templates;
+pub mod xpath;
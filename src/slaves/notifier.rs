@@ -1,43 +1,513 @@
-use std::{fmt::Display, fs};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fmt::Display,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{anyhow, Result};
 
-use rutebot::{client::Rutebot, requests::SendMessage};
-use serde::Deserialize;
+use crate::slaves::{
+    commands::{Command, CommandHandler, USAGE},
+    config_parser::load_yaml,
+    fetchers::AimInfo,
+};
+use rutebot::{
+    client::Rutebot,
+    requests::{GetUpdates, Request, UpdateKind},
+    responses::{Message, Update},
+};
+use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::mpsc::{self, Receiver, Sender},
+    sync::mpsc::{self, Receiver, Sender, UnboundedSender},
     task::JoinHandle,
 };
 
+/// Telegram doesn't accept longer messages.
+const MESSAGE_LIMIT: usize = 4096;
+
 #[derive(Clone, Copy, Debug)]
 pub enum Signal<T: Display = String> {
-    Action(T),
+    /// Sent as is, formatted by the sender for the format of the chat
     Msg(T),
-    Err(T),
+    Warn(T),
+}
+
+impl<T: Display> Signal<T> {
+    /// `msg` or `warn`, as used by the `silent` list of the config.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Signal::Msg(_) => "msg",
+            Signal::Warn(_) => "warn",
+        }
+    }
+
+    /// Text in the format, the prefix is made bold and the rest escaped unless it's a `Msg`.
+    fn format(&self, format: TgFormat) -> String {
+        let (prefix, msg) = match self {
+            Signal::Msg(msg) => return msg.to_string(),
+            Signal::Warn(msg) => ("Warning:", msg),
+        };
+        format!(
+            "{} {}",
+            format.bold(prefix),
+            (format.escape())(&msg.to_string())
+        )
+    }
 }
 
 impl Display for Signal {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Signal::Action(msg) => write!(f, "Action required: {}", msg),
             Signal::Msg(msg) => write!(f, "{}", msg),
-            Signal::Err(msg) => write!(f, "Error occured: {}", msg),
+            Signal::Warn(msg) => write!(f, "Warning: {}", msg),
+        }
+    }
+}
+
+/// Markup of the messages, values put into message templates are escaped for it.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TgFormat {
+    #[default]
+    Plain,
+    MarkdownV2,
+    #[serde(alias = "HTML")]
+    Html,
+}
+
+impl TgFormat {
+    fn parse_mode(self) -> Option<&'static str> {
+        match self {
+            TgFormat::Plain => None,
+            TgFormat::MarkdownV2 => Some("MarkdownV2"),
+            TgFormat::Html => Some("HTML"),
+        }
+    }
+
+    pub fn escape(self) -> fn(&str) -> String {
+        match self {
+            TgFormat::Plain => str::to_string,
+            TgFormat::MarkdownV2 => escape_markdown,
+            TgFormat::Html => escape_html,
+        }
+    }
+
+    fn bold(self, text: &str) -> String {
+        match self {
+            TgFormat::Plain => text.to_string(),
+            TgFormat::MarkdownV2 => format!("*{}*", escape_markdown(text)),
+            TgFormat::Html => format!("<b>{}</b>", escape_html(text)),
+        }
+    }
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Piece of a message that is never cut: a character, an escape, an HTML entity, a link or the
+/// markup starting or ending an entity.
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    /// Markup starting an entity and the markup ending it
+    Open(String, String),
+    Close(String),
+}
+
+/// Length of the `[text](url)` link at the start of `text`, escapes taken into account.
+fn link_len(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().skip(1);
+    let mut in_url = false;
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            ']' if !in_url && text[at + 1..].starts_with('(') => {
+                in_url = true;
+                chars.next();
+            }
+            ']' if !in_url => return None,
+            ')' if in_url => return Some(at + 1),
+            _ => {}
         }
     }
+    None
+}
+
+fn markdown_pieces(text: &str) -> Vec<Piece> {
+    let mut pieces = vec![];
+    let mut open: Vec<&str> = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let in_code = matches!(open.last(), Some(&"`") | Some(&"```"));
+        let marker = ["```", "`", "||", "__", "_", "*", "~"]
+            .iter()
+            .copied()
+            .find(|marker| rest.starts_with(marker))
+            .filter(|marker| !in_code || open.last() == Some(marker));
+        let (piece, len) = match (c, marker) {
+            ('\\', _) => {
+                let len = rest.chars().take(2).map(char::len_utf8).sum();
+                (Piece::Text(rest[..len].to_string()), len)
+            }
+            (_, Some(marker)) if open.contains(&marker) => {
+                let at = open.iter().rposition(|open| *open == marker).unwrap();
+                open.remove(at);
+                (Piece::Close(marker.to_string()), marker.len())
+            }
+            // the language line belongs to the start of a code block
+            (_, Some("```")) => {
+                let len = rest.find('\n').map_or(rest.len(), |at| at + 1);
+                open.push("```");
+                (Piece::Open(rest[..len].to_string(), "```".to_string()), len)
+            }
+            (_, Some(marker)) => {
+                open.push(marker);
+                (
+                    Piece::Open(marker.to_string(), marker.to_string()),
+                    marker.len(),
+                )
+            }
+            ('[', None) if !in_code => {
+                let len = link_len(rest).unwrap_or(1);
+                (Piece::Text(rest[..len].to_string()), len)
+            }
+            _ => (Piece::Text(c.to_string()), c.len_utf8()),
+        };
+        pieces.push(piece);
+        rest = &rest[len..];
+    }
+    pieces
+}
+
+fn html_pieces(text: &str) -> Vec<Piece> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let tag = rest.find('>').filter(|_| c == '<').map(|end| &rest[..=end]);
+        let entity = rest
+            .find(';')
+            .filter(|&end| c == '&' && !rest[..end].contains(char::is_whitespace))
+            .map(|end| &rest[..=end]);
+        let piece = match (tag, entity) {
+            (Some(tag), _) if tag.starts_with("</") => Piece::Close(tag.to_string()),
+            (Some(tag), _) => {
+                let name = tag[1..tag.len() - 1]
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or_default();
+                Piece::Open(tag.to_string(), format!("</{}>", name))
+            }
+            (None, Some(entity)) => Piece::Text(entity.to_string()),
+            _ => Piece::Text(c.to_string()),
+        };
+        rest = match &piece {
+            Piece::Text(text) | Piece::Open(text, _) | Piece::Close(text) => &rest[text.len()..],
+        };
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// Markup of the entities open at some point of a message, with the markup ending each of them.
+type OpenEntities = Vec<(String, String)>;
+
+fn openers(open: &OpenEntities) -> String {
+    open.iter().map(|(opener, _)| opener.as_str()).collect()
+}
+
+fn closers(open: &OpenEntities) -> String {
+    open.iter()
+        .rev()
+        .map(|(_, closer)| closer.as_str())
+        .collect()
+}
+
+/// Parts of at most `limit` characters, split at line breaks or spaces where possible. Entities
+/// cut apart are ended in one part and started again in the next, so each part is valid markup.
+fn split_message(text: &str, limit: usize, format: TgFormat) -> Vec<String> {
+    let pieces = match format {
+        TgFormat::Plain => text.chars().map(|c| Piece::Text(c.to_string())).collect(),
+        TgFormat::MarkdownV2 => markdown_pieces(text),
+        TgFormat::Html => html_pieces(text),
+    };
+    let mut parts = vec![];
+    let mut open = OpenEntities::new();
+    // entities open at the start of the current part, its pieces with the entities open after them
+    // and whether they start one
+    let mut start = OpenEntities::new();
+    let mut part: Vec<(String, OpenEntities, bool)> = vec![];
+    let mut part_len = 0;
+    let len = |start: &OpenEntities, part_len: usize, open: &OpenEntities| {
+        openers(start).chars().count() + part_len + closers(open).chars().count()
+    };
+    for piece in pieces {
+        let (text, opens) = match piece {
+            Piece::Text(text) => (text, false),
+            Piece::Open(opener, closer) => {
+                open.push((opener.clone(), closer));
+                (opener, true)
+            }
+            Piece::Close(closer) => {
+                if let Some(at) = open.iter().rposition(|(_, open)| *open == closer) {
+                    open.remove(at);
+                }
+                (closer, false)
+            }
+        };
+        part_len += text.chars().count();
+        part.push((text, open.clone(), opens));
+        while len(&start, part_len, &open) > limit {
+            // a part never ends with the start of an empty entity
+            let can_end = |at: usize| !part[at - 1].2;
+            let space = |space: &str| {
+                (1..part.len())
+                    .rev()
+                    .find(|&at| part[at].0 == space && can_end(at))
+            };
+            let (end, next) = match space("\n").or_else(|| space(" ")) {
+                Some(at) => (at, at + 1),
+                None => match (1..part.len()).rev().find(|&at| can_end(at)) {
+                    Some(at) => (at, at),
+                    // too long to split
+                    None => break,
+                },
+            };
+            let head: String = part[..end].iter().map(|(text, ..)| text.as_str()).collect();
+            let head_open = &part[end - 1].1;
+            parts.push(format!("{}{}{}", openers(&start), head, closers(head_open)));
+            start = head_open.clone();
+            part.drain(..next);
+            part_len = part.iter().map(|(text, ..)| text.chars().count()).sum();
+        }
+    }
+    let rest: String = part.iter().map(|(text, ..)| text.as_str()).collect();
+    if parts.is_empty() || !rest.is_empty() {
+        parts.push(format!("{}{}", openers(&start), rest));
+    }
+    parts
+}
+
+/// `sendMessage` with the parse modes rutebot doesn't know about.
+#[derive(Serialize)]
+struct SendFormatted<'a> {
+    chat_id: &'a str,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<&'static str>,
+    disable_web_page_preview: bool,
+    disable_notification: bool,
+}
+
+impl<'a> Request for SendFormatted<'a> {
+    type ResponseType = Message;
+
+    fn method(&self) -> &'static str {
+        "sendMessage"
+    }
 }
 
 #[derive(Clone)]
-struct RutebotWrapper(Rutebot, String);
+struct RutebotWrapper(Rutebot, TgConfig);
+
+/// Time of the day when messages come without a sound, `from: "23:00"`, `to: "08:00"`.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "QuietHoursConfig")]
+pub struct QuietHours {
+    /// Minutes since midnight
+    from: i64,
+    to: i64,
+    /// Minutes the times are ahead of UTC
+    utc_offset: i64,
+}
 
 #[derive(Deserialize)]
+struct QuietHoursConfig {
+    from: String,
+    to: String,
+    /// Like `+05:30` for the times 5.5 hours ahead of UTC
+    #[serde(default)]
+    utc_offset: Option<String>,
+}
+
+impl TryFrom<QuietHoursConfig> for QuietHours {
+    type Error = anyhow::Error;
+
+    fn try_from(config: QuietHoursConfig) -> Result<Self> {
+        let time = |time: &str| {
+            Self::minutes(time).ok_or_else(|| anyhow!("Quiet hours time {} isn't HH:MM", time))
+        };
+        let utc_offset = match &config.utc_offset {
+            Some(offset) => match offset.trim().strip_prefix('-') {
+                Some(offset) => Self::minutes(offset).map(|minutes| -minutes),
+                None => Self::minutes(offset.trim().trim_start_matches('+')),
+            }
+            .ok_or_else(|| anyhow!("UTC offset {} isn't like +05:30", offset))?,
+            None => 0,
+        };
+        Ok(QuietHours {
+            from: time(&config.from)?,
+            to: time(&config.to)?,
+            utc_offset,
+        })
+    }
+}
+
+impl QuietHours {
+    fn minutes(time: &str) -> Option<i64> {
+        let (hours, minutes) = time.trim().split_once(':')?;
+        let digits =
+            |part: &str| (1..=2).contains(&part.len()) && part.chars().all(|c| c.is_ascii_digit());
+        if !digits(hours) || minutes.len() != 2 || !digits(minutes) {
+            return None;
+        }
+        let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+        Some(hours * 60 + minutes).filter(|_| hours < 24 && minutes < 60)
+    }
+
+    fn contains(&self, time: SystemTime) -> bool {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64
+            + self.utc_offset * 60;
+        let minute = secs.rem_euclid(24 * 3600) / 60;
+        match self.from <= self.to {
+            true => self.from <= minute && minute < self.to,
+            // over midnight
+            false => minute >= self.from || minute < self.to,
+        }
+    }
+}
+
+/// Chat the messages go to.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ChatConfig {
+    pub chat_id: String,
+    #[serde(default)]
+    pub format: TgFormat,
+    #[serde(default = "TgConfig::default_link_previews")]
+    link_previews: bool,
+    /// Signals sent without a sound, see `Signal::kind`
+    #[serde(default)]
+    silent: Vec<String>,
+    #[serde(default)]
+    quiet_hours: Option<QuietHours>,
+    /// Results of the aims with any of these tags are sent to the chat
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Results of these aims are sent to the chat, all results are without `tags` and `aims`
+    #[serde(default)]
+    aims: Vec<String>,
+    /// Whether commands are taken from the chat
+    #[serde(default)]
+    commands: bool,
+}
+
+impl ChatConfig {
+    pub fn subscribed(&self, aim: &AimInfo) -> bool {
+        (self.tags.is_empty() && self.aims.is_empty())
+            || self.aims.contains(&aim.id)
+            || self.tags.iter().any(|tag| aim.tags.contains(tag))
+    }
+
+    fn silent(&self, kind: &str, time: SystemTime) -> bool {
+        self.silent.iter().any(|silent| silent == kind)
+            || self
+                .quiet_hours
+                .as_ref()
+                .is_some_and(|quiet| quiet.contains(time))
+    }
+}
+
+#[derive(Deserialize, Clone)]
 pub struct TgConfig {
     token: String,
-    chat_id: String,
+    /// A chat set up by the options below, `chats` have their own
+    #[serde(default)]
+    chat_id: Option<String>,
+    #[serde(default)]
+    format: TgFormat,
+    #[serde(default = "TgConfig::default_link_previews")]
+    link_previews: bool,
+    /// Signals sent without a sound, see `Signal::kind`
+    #[serde(default)]
+    silent: Vec<String>,
+    /// Whether to poll for commands, see `commands`, off so the bot may have a webhook
+    #[serde(default)]
+    commands: bool,
+    /// Chats besides the recipients the commands are taken from
+    #[serde(default)]
+    commands_from: Vec<String>,
+    /// Personal chats, groups and channels with their own subscriptions and formats
+    #[serde(default)]
+    chats: Vec<ChatConfig>,
+}
+
+impl TgConfig {
+    fn default_link_previews() -> bool {
+        true
+    }
+
+    /// Settings of a chat that isn't in `chats`, the top level ones.
+    fn chat(&self, chat_id: &str) -> ChatConfig {
+        ChatConfig {
+            chat_id: chat_id.to_string(),
+            format: self.format,
+            link_previews: self.link_previews,
+            silent: self.silent.clone(),
+            quiet_hours: None,
+            tags: vec![],
+            aims: vec![],
+            commands: true,
+        }
+    }
+
+    /// `chat_id` and `chats` together.
+    fn recipients(&self) -> Vec<ChatConfig> {
+        self.chat_id
+            .iter()
+            .map(|chat_id| self.chat(chat_id))
+            .chain(self.chats.iter().cloned())
+            .collect()
+    }
+
+    fn recipient(&self, chat_id: &str) -> Option<ChatConfig> {
+        self.recipients()
+            .into_iter()
+            .find(|chat| chat.chat_id == chat_id)
+    }
+
+    fn authorized(&self, chat_id: &str) -> bool {
+        self.recipient(chat_id).is_some_and(|chat| chat.commands)
+            || self.commands_from.iter().any(|chat| chat == chat_id)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct TgNotifier<T: Display + Send = String> {
-    tx: Sender<Signal<T>>,
+    /// Signals with the chat they go to
+    tx: Sender<(String, Signal<T>)>,
+    handlers: Sender<Arc<dyn CommandHandler>>,
+    recipients: Vec<ChatConfig>,
 }
 
 impl<T> TgNotifier<T>
@@ -51,47 +521,258 @@ where
 
     pub fn new_with_loop_handle() -> Result<(Self, JoinHandle<()>)> {
         let (tx, rx) = mpsc::channel(32);
+        let (handlers, handlers_rx) = mpsc::channel(1);
         let bot = Self::create_bot_from_conf()?;
-        Ok((Self { tx }, Self::create_channel_loop(rx, bot)))
+        let recipients = bot.1.recipients();
+        if recipients.is_empty() {
+            return Err(anyhow!("config/tg.yaml has neither chat_id nor chats"));
+        }
+        let notifier = Self {
+            tx,
+            handlers,
+            recipients,
+        };
+        Ok((notifier, Self::create_channel_loop(rx, handlers_rx, bot)))
     }
 
     fn create_bot_from_conf() -> Result<RutebotWrapper> {
-        let content = fs::read_to_string("config/tg.yaml")?;
-        let conf: TgConfig = serde_yaml::from_str(&content)?;
-        Ok(RutebotWrapper(Rutebot::new(conf.token), conf.chat_id))
+        let conf: TgConfig = load_yaml(Path::new("config/tg.yaml"))?;
+        Ok(RutebotWrapper(Rutebot::new(conf.token.clone()), conf))
     }
 
-    fn create_channel_loop(mut rx: Receiver<Signal<T>>, bot: RutebotWrapper) -> JoinHandle<()> {
+    /// Sends the signals and, once there is a command handler, polls for commands in between.
+    fn create_channel_loop(
+        mut rx: Receiver<(String, Signal<T>)>,
+        mut handlers: Receiver<Arc<dyn CommandHandler>>,
+        bot: RutebotWrapper,
+    ) -> JoinHandle<()> {
         tokio::spawn(async move {
-            while let Some(signal) = rx.recv().await {
-                Self::process_signal(signal, &bot).await.unwrap();
+            let mut handler: Option<Arc<dyn CommandHandler>> = None;
+            let mut chats = HashMap::new();
+            let mut offset = None;
+            let mut retry_at = Instant::now();
+            loop {
+                let polling = handler.is_some() && bot.1.commands;
+                tokio::select! {
+                    signal = rx.recv() => match signal {
+                        // one message Telegram refuses shouldn't stop the others
+                        Some((chat, signal)) => Self::process_signal(chat, signal, &bot).await,
+                        None => break,
+                    },
+                    Some(new_handler) = handlers.recv() => {
+                        // the chats get new queues for the new handler
+                        chats.clear();
+                        handler = Some(new_handler);
+                    }
+                    updates = Self::poll(&bot, offset, retry_at), if polling => match updates {
+                        Ok(updates) => {
+                            for update in updates {
+                                offset = Some(update.update_id + 1);
+                                if let Some(handler) = &handler {
+                                    Self::queue_update(&mut chats, update, handler, &bot);
+                                }
+                            }
+                        }
+                        Err(err) => {
+                            eprintln!("Couldn't get Telegram updates: {:?}", err);
+                            retry_at = Instant::now() + Duration::from_secs(5);
+                        }
+                    },
+                }
             }
         })
     }
 
-    pub async fn send(&self, signal: Signal<T>) -> Result<()> {
+    /// Waits for new messages to the bot. Dropped whenever a signal comes, the updates are
+    /// confirmed only by the `offset` of the next call, so none get lost.
+    async fn poll(
+        bot: &RutebotWrapper,
+        offset: Option<i64>,
+        retry_at: Instant,
+    ) -> Result<Vec<Update>> {
+        tokio::time::sleep_until(retry_at.into()).await;
+        let request = GetUpdates {
+            offset,
+            timeout: Some(25),
+            allowed_updates: Some(&[UpdateKind::Message]),
+            ..Default::default()
+        };
+        Ok(bot.0.prepare_api_request(request).send().await?)
+    }
+
+    /// Hands the command of the update to the queue of its chat. Commands may take a while, the
+    /// queue has its own task so signals and polling go on meanwhile, and a chat's commands are
+    /// carried out in order.
+    fn queue_update(
+        chats: &mut HashMap<i64, UnboundedSender<Result<Command, String>>>,
+        update: Update,
+        handler: &Arc<dyn CommandHandler>,
+        bot: &RutebotWrapper,
+    ) {
+        let message = match update.message {
+            Some(message) => message,
+            None => return,
+        };
+        let chat_id = message.chat.id;
+        let command = match message.text.as_deref().and_then(Command::parse) {
+            Some(Ok(Command::Text(_))) | None => return,
+            Some(command) => command,
+        };
+        // nothing is carried out for unknown chats, they don't get a queue
+        if !bot.1.authorized(&chat_id.to_string()) {
+            eprintln!("Ignored a command from unknown chat {}", chat_id);
+            return;
+        }
+        let queue = chats.entry(chat_id).or_insert_with(|| {
+            let (queue, mut commands) = mpsc::unbounded_channel();
+            let (handler, bot) = (handler.clone(), bot.clone());
+            tokio::spawn(async move {
+                let chat_id = chat_id.to_string();
+                while let Some(command) = commands.recv().await {
+                    Self::process_command(&chat_id, command, handler.as_ref(), &bot).await;
+                }
+            });
+            queue
+        });
+        if queue.send(command).is_err() {
+            eprintln!("Commands of chat {} aren't taken anymore", chat_id);
+        }
+    }
+
+    /// Carries out a command from an authorized chat and replies to it.
+    async fn process_command(
+        chat_id: &str,
+        command: Result<Command, String>,
+        handler: &dyn CommandHandler,
+        bot: &RutebotWrapper,
+    ) {
+        let reply = match command {
+            Ok(Command::Help) => USAGE.to_string(),
+            Ok(command) => handler.handle(chat_id, command).await,
+            Err(usage) => usage,
+        };
+        if reply.is_empty() {
+            return;
+        }
+        let chat = bot
+            .1
+            .recipient(chat_id)
+            .unwrap_or_else(|| bot.1.chat(chat_id));
+        let reply = (chat.format.escape())(&reply);
+        if let Err(err) = Self::send_text(bot, &chat, &reply, false).await {
+            eprintln!("Couldn't reply to chat {}: {:?}", chat_id, err);
+        }
+    }
+
+    /// Takes commands to the bot and hands them to `handler`.
+    pub async fn serve(&self, handler: Arc<dyn CommandHandler>) -> Result<()> {
+        self.handlers
+            .send(handler)
+            .await
+            .map_err(|_| anyhow!("Send error"))
+    }
+
+    /// Chats the messages go to, `Signal::Msg` texts are expected in the format of the chat.
+    pub fn recipients(&self) -> &[ChatConfig] {
+        &self.recipients
+    }
+
+    /// Sends the signal about the aim to every recipient subscribed to it.
+    pub async fn send(&self, aim: &AimInfo, signal: Signal<T>) -> Result<()>
+    where
+        T: Clone,
+    {
+        for chat in self.recipients.iter().filter(|chat| chat.subscribed(aim)) {
+            self.send_to(&chat.chat_id, signal.clone()).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn send_to(&self, chat_id: &str, signal: Signal<T>) -> Result<()> {
         self.tx
-            .send(signal)
+            .send((chat_id.to_string(), signal))
             .await
             .map_err(|_| anyhow!("Send error"))
     }
 
-    async fn process_signal(signal: Signal<T>, bot: &RutebotWrapper) -> Result<()> {
+    async fn process_signal(chat_id: String, signal: Signal<T>, bot: &RutebotWrapper) {
         println!("{}", signal);
-        let msg = &signal.to_string()[..];
-        bot.0
-            .prepare_api_request(SendMessage::new(&bot.1[..], msg))
-            .send()
-            .await?;
+        let now = SystemTime::now();
+        let recipients = bot
+            .1
+            .recipients()
+            .into_iter()
+            .filter(|chat| chat.chat_id == chat_id);
+        for chat in recipients {
+            let silent = chat.silent(signal.kind(), now);
+            // one message Telegram refuses shouldn't stop the others
+            if let Err(err) = Self::send_text(bot, &chat, &signal.format(chat.format), silent).await
+            {
+                eprintln!(
+                    "Couldn't send a Telegram message to {}: {:?}",
+                    chat.chat_id, err
+                );
+            }
+        }
+    }
+
+    /// Sends text already in the configured format, in several messages when it's too long.
+    async fn send_text(
+        bot: &RutebotWrapper,
+        chat: &ChatConfig,
+        text: &str,
+        silent: bool,
+    ) -> Result<()> {
+        for part in split_message(text, MESSAGE_LIMIT, chat.format) {
+            bot.0
+                .prepare_api_request(SendFormatted {
+                    chat_id: &chat.chat_id,
+                    text: &part,
+                    parse_mode: chat.format.parse_mode(),
+                    disable_web_page_preview: !chat.link_previews,
+                    disable_notification: silent,
+                })
+                .send()
+                .await?;
+        }
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use async_trait::async_trait;
+    use rutebot::{client::Rutebot, responses::Update};
+
+    use crate::slaves::{
+        commands::{Command, CommandHandler},
+        fetchers::AimInfo,
+    };
+
+    use super::{
+        split_message, QuietHours, RutebotWrapper, Signal::*, TgConfig, TgFormat, TgNotifier,
+    };
+
+    /// Takes its time over `/run`, answers nothing so nothing is sent.
+    #[derive(Default)]
+    struct SlowHandler(Mutex<Vec<(String, Command)>>);
 
-    use super::{Signal::*, TgNotifier};
+    #[async_trait]
+    impl CommandHandler for SlowHandler {
+        async fn handle(&self, chat: &str, command: Command) -> String {
+            if let Command::Run(_) = command {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            self.0.lock().unwrap().push((chat.to_string(), command));
+            String::new()
+        }
+    }
 
     use futures::{future::try_join_all, FutureExt};
 
@@ -99,19 +780,201 @@ mod tests {
     async fn test_notifier() {
         let (notifier, loop_h) = TgNotifier::new_with_loop_handle().unwrap();
         let notifier2 = notifier.clone();
+        let aim = AimInfo::default();
 
         let futures = vec![
             async move {
                 tokio::time::sleep(Duration::from_secs(1)).await;
-                notifier2.send(Msg("Hello, World!".to_string())).await
+                notifier2
+                    .send(&AimInfo::default(), Msg("Hello, World!".to_string()))
+                    .await
             }
             .boxed(),
-            notifier.send(Action("You must do it".to_string())).boxed(),
-            notifier.send(Err("Crashed".to_string())).boxed(),
+            notifier
+                .send(&aim, Warn("Price went up".to_string()))
+                .boxed(),
         ];
 
         try_join_all(futures).await.unwrap();
         drop(notifier);
         loop_h.await.unwrap();
     }
+
+    #[test]
+    fn test_format() {
+        let warning = Warn("Price of a.b (old) is <1_000>".to_string());
+        assert_eq!(
+            warning.format(TgFormat::MarkdownV2),
+            r"*Warning:* Price of a\.b \(old\) is <1\_000\>"
+        );
+        assert_eq!(
+            warning.format(TgFormat::Html),
+            "<b>Warning:</b> Price of a.b (old) is &lt;1_000&gt;"
+        );
+        assert_eq!(warning.format(TgFormat::Plain), warning.to_string());
+        assert_eq!(
+            Msg("*as is*".to_string()).format(TgFormat::MarkdownV2),
+            "*as is*"
+        );
+    }
+
+    #[test]
+    fn test_split_message() {
+        let plain = |text, limit| split_message(text, limit, TgFormat::Plain);
+        assert_eq!(plain("short", 10), vec!["short"]);
+        assert_eq!(
+            plain("first line\nsecond line", 15),
+            vec!["first line", "second line"]
+        );
+        assert_eq!(plain("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        let long = "ы".repeat(5000);
+        let parts = plain(&long, 4096);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].chars().count(), 4096);
+
+        // every part ends the entities it starts
+        assert_eq!(
+            split_message("<b>bold text here</b> end", 16, TgFormat::Html),
+            vec!["<b>bold text</b>", "<b>here</b> end"]
+        );
+        assert_eq!(
+            split_message(r#"<a href="u"><i>ab</i>cd</a>"#, 24, TgFormat::Html),
+            vec![
+                r#"<a href="u"><i>a</i></a>"#,
+                r#"<a href="u"><i>b</i></a>"#,
+                r#"<a href="u">cd</a>"#
+            ]
+        );
+        assert_eq!(
+            split_message("*bold text* end", 10, TgFormat::MarkdownV2),
+            vec!["*bold*", "*text* end"]
+        );
+        assert_eq!(
+            split_message("```rust\nlet a;\nlet b;\n```", 20, TgFormat::MarkdownV2),
+            vec!["```rust\nlet a;```", "```rust\nlet b;\n```"]
+        );
+        // neither an entity nor an escape is torn apart
+        assert_eq!(
+            split_message("abc&amp;d", 5, TgFormat::Html),
+            vec!["abc", "&amp;", "d"]
+        );
+        assert_eq!(
+            split_message(r"ab\.cd", 3, TgFormat::MarkdownV2),
+            vec!["ab", r"\.c", "d"]
+        );
+    }
+
+    #[test]
+    fn test_quiet_hours() {
+        let at = |hours: u64, minutes: u64| {
+            UNIX_EPOCH + Duration::from_secs(hours * 3600 + minutes * 60)
+        };
+        let quiet = |yaml: &str| serde_yaml::from_str::<QuietHours>(yaml);
+        let night = quiet("{from: '23:00', to: '08:00', utc_offset: '+03:00'}").unwrap();
+        assert!(night.contains(at(20, 0)));
+        assert!(night.contains(at(4, 59)));
+        assert!(!night.contains(at(5, 0)));
+        assert!(!night.contains(at(12, 0)));
+        let lunch = quiet("{from: '13:00', to: '14:00'}").unwrap();
+        assert!(lunch.contains(at(13, 30)));
+        assert!(!lunch.contains(at(14, 0)));
+        let india = quiet("{from: '22:00', to: '7:00', utc_offset: '+05:30'}").unwrap();
+        assert!(india.contains(at(16, 30)));
+        assert!(!india.contains(at(16, 29)));
+        let west = quiet("{from: '22:00', to: '07:00', utc_offset: '-03:30'}").unwrap();
+        assert!(west.contains(at(1, 30)));
+        assert!(!west.contains(at(1, 29)));
+
+        assert!(quiet("{from: '25:99', to: '08:00'}").is_err());
+        assert!(quiet("{from: '23:00', to: '8am'}").is_err());
+        assert!(quiet("{from: '23:00', to: '08:00', utc_offset: '3'}").is_err());
+    }
+
+    #[test]
+    fn test_recipients() {
+        let config: TgConfig = serde_yaml::from_str(
+            r#"
+token: "token"
+chat_id: "1"
+format: HTML
+chats:
+  - chat_id: "-100"
+    format: MarkdownV2
+    tags: [shops]
+    aims: [kettle]
+    quiet_hours: {from: "23:00", to: "08:00"}
+"#,
+        )
+        .unwrap();
+        let recipients = config.recipients();
+        assert_eq!(recipients.len(), 2);
+        assert_eq!(recipients[0].format, TgFormat::Html);
+        assert_eq!(recipients[1].format, TgFormat::MarkdownV2);
+        // results make a sound unless the config says otherwise
+        assert!(!recipients[0].silent(Msg(String::new()).kind(), UNIX_EPOCH));
+        assert!(!config.commands);
+        assert!(config.authorized("1"));
+        assert!(!config.authorized("-100"));
+        assert!(!config.authorized("2"));
+
+        let aim = |id: &str, tags: &[&str]| AimInfo {
+            id: id.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Default::default()
+        };
+        assert!(recipients[0].subscribed(&aim("news", &[])));
+        assert!(recipients[1].subscribed(&aim("kettle", &[])));
+        assert!(recipients[1].subscribed(&aim("tv", &["shops"])));
+        assert!(!recipients[1].subscribed(&aim("news", &["media"])));
+    }
+
+    #[tokio::test]
+    async fn test_command_order() {
+        let config: TgConfig = serde_yaml::from_str(
+            "token: token\nchats: [{chat_id: '1'}, {chat_id: '2'}]\ncommands_from: ['1', '2']",
+        )
+        .unwrap();
+        let bot = RutebotWrapper(Rutebot::new("token"), config);
+        let update = |id: i64, chat: i64, text: &str| {
+            serde_json::from_value::<Update>(serde_json::json!({
+                "update_id": id,
+                "message": {
+                    "message_id": id,
+                    "date": 0,
+                    "chat": {"id": chat, "type": "private"},
+                    "text": text,
+                },
+            }))
+            .unwrap()
+        };
+        let recorder = Arc::new(SlowHandler::default());
+        let handler: Arc<dyn CommandHandler> = recorder.clone();
+        let mut chats = HashMap::new();
+        for update in [
+            update(1, 1, "/run kettle"),
+            update(2, 1, "/pause kettle"),
+            update(3, 2, "/list"),
+        ] {
+            TgNotifier::<String>::queue_update(&mut chats, update, &handler, &bot);
+        }
+        tokio::time::sleep(Duration::from_millis(400)).await;
+
+        let handled = recorder.0.lock().unwrap().clone();
+        let chat = |id: &str| -> Vec<_> {
+            handled
+                .iter()
+                .filter(|(chat, _)| chat == id)
+                .map(|(_, command)| command.clone())
+                .collect()
+        };
+        // the slow command holds up its own chat only
+        assert_eq!(handled[0], ("2".to_string(), Command::List));
+        assert_eq!(
+            chat("1"),
+            vec![
+                Command::Run("kettle".to_string()),
+                Command::Pause("kettle".to_string())
+            ]
+        );
+    }
 }
@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+
+use super::{
+    clients::{cache::NotModified, http::AimClient},
+    fetchers::FetcherConfig,
+};
+
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PageMode {
+    /// CSS selector of the link to the next page
+    Next(String),
+    /// Url with a `{page}` placeholder, used for every page instead of the aim url
+    Template {
+        url: String,
+        #[serde(default = "PageMode::default_first")]
+        first: u32,
+    },
+    /// Query parameter set to `0`, `limit`, `2 * limit`... on the aim url
+    Offset {
+        param: String,
+        limit: u32,
+        /// Parameter the page size is sent in, if the site needs one
+        #[serde(default)]
+        limit_param: Option<String>,
+    },
+}
+
+impl PageMode {
+    fn default_first() -> u32 {
+        1
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct PaginationConfig {
+    #[serde(flatten)]
+    pub mode: PageMode,
+    #[serde(default = "PaginationConfig::default_max_pages")]
+    pub max_pages: u32,
+}
+
+impl PaginationConfig {
+    fn default_max_pages() -> u32 {
+        10
+    }
+
+    /// Url of the page with the given index, counted from 0. `None` when it is only known from the
+    /// previous page.
+    fn page_url(&self, aim_url: &str, index: u32) -> Result<Option<Url>> {
+        match &self.mode {
+            PageMode::Next(_) if index == 0 => Ok(Some(Url::parse(aim_url)?)),
+            PageMode::Next(_) => Ok(None),
+            PageMode::Template { url, first } => Ok(Some(Url::parse(
+                &url.replace("{page}", &(first + index).to_string()),
+            )?)),
+            PageMode::Offset {
+                param,
+                limit,
+                limit_param,
+            } => {
+                let mut url = Url::parse(aim_url)?;
+                let mut pairs: Vec<(String, String)> = url
+                    .query_pairs()
+                    .filter(|(name, _)| {
+                        name != param && Some(name.as_ref()) != limit_param.as_deref()
+                    })
+                    .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                    .collect();
+                pairs.push((param.clone(), (limit * index).to_string()));
+                if let Some(limit_param) = limit_param {
+                    pairs.push((limit_param.clone(), limit.to_string()));
+                }
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+                Ok(Some(url))
+            }
+        }
+    }
+
+    fn next_link(&self, page_url: &Url, tree: &Html) -> Result<Option<Url>> {
+        let selector = match &self.mode {
+            PageMode::Next(selector) => selector,
+            _ => return Ok(None),
+        };
+        let selector =
+            Selector::parse(selector).map_err(|x| anyhow!("Selector parsing errored {:?}", x))?;
+        Ok(tree
+            .select(&selector)
+            .next()
+            .and_then(|link| link.value().attr("href"))
+            .and_then(|href| page_url.join(href).ok()))
+    }
+}
+
+/// Reads every page of the aim with `read`, only the aim url without a `pagination` section.
+/// A page `read` finds nothing on or a link back to a page already read is taken as the end of
+/// the list.
+pub async fn read_pages<T, F>(
+    client: &AimClient,
+    config: &FetcherConfig,
+    mut read: F,
+) -> Result<Vec<T>>
+where
+    T: Send,
+    F: FnMut(&Url, &Html) -> Option<T> + Send,
+{
+    let mut read_all = vec![];
+    let mut url = match &config.pagination {
+        Some(pagination) => pagination.page_url(&config.url, 0)?,
+        None => Some(Url::parse(&config.url)?),
+    };
+    let mut index = 0;
+    let mut visited = HashSet::new();
+    while let Some(page_url) = url.take() {
+        if !visited.insert(page_url.clone()) {
+            break;
+        }
+        let text = match client.get_text(page_url.as_str()).await {
+            Ok(text) => text,
+            // nothing changed since the previous fetch, so there is nothing new to report
+            Err(err) if index == 0 && err.is::<NotModified>() => break,
+            Err(err) if index == 0 => return Err(err),
+            Err(err) => match err.downcast::<NotModified>() {
+                // the list changed, its pages that didn't are read from the last response
+                Ok(NotModified(text)) => text,
+                Err(err) => {
+                    eprintln!("Couldn't fetch {}: {:?}", page_url, err);
+                    break;
+                }
+            },
+        };
+        let tree = Html::parse_document(&text);
+        match read(&page_url, &tree) {
+            Some(page) => read_all.push(page),
+            None => break,
+        }
+        index += 1;
+        if let Some(pagination) = config
+            .pagination
+            .as_ref()
+            .filter(|pagination| index < pagination.max_pages)
+        {
+            url = match pagination.next_link(&page_url, &tree)? {
+                Some(next) => Some(next),
+                None => pagination.page_url(&config.url, index)?,
+            };
+        }
+    }
+    Ok(read_all)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slaves::{
+        clients::http::tests::{response, serve},
+        fetchers::{
+            FetchItem, Fetchable, FetcherConfig, FoundItem, FoundItemContent, SimpleFetcher,
+        },
+    };
+
+    use super::{PageMode, PaginationConfig};
+
+    fn price_fetcher(url: String) -> SimpleFetcher {
+        SimpleFetcher {
+            config: FetcherConfig {
+                url,
+                items: vec![FetchItem {
+                    name: "price".to_string(),
+                    path: vec!["b".to_string()],
+                    primary: true,
+                    ..Default::default()
+                }],
+                pagination: Some(PaginationConfig {
+                    mode: PageMode::Next("a.next".to_string()),
+                    max_pages: 5,
+                }),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn prices(fetched: Vec<Option<FoundItem>>) -> Vec<FoundItemContent> {
+        fetched
+            .into_iter()
+            .map(|found| found.unwrap().content)
+            .collect()
+    }
+
+    #[test]
+    fn test_page_url() {
+        let template: PaginationConfig = serde_yaml::from_str(
+            "template:\n  url: http://shop.example/c?page={page}\nmax_pages: 3",
+        )
+        .unwrap();
+        assert_eq!(
+            template
+                .page_url("http://shop.example/", 1)
+                .unwrap()
+                .unwrap()
+                .as_str(),
+            "http://shop.example/c?page=2"
+        );
+
+        let offset = PaginationConfig {
+            mode: PageMode::Offset {
+                param: "offset".to_string(),
+                limit: 20,
+                limit_param: Some("limit".to_string()),
+            },
+            max_pages: 3,
+        };
+        assert_eq!(
+            offset
+                .page_url("http://shop.example/c?q=kettle&offset=5", 2)
+                .unwrap()
+                .unwrap()
+                .as_str(),
+            "http://shop.example/c?q=kettle&offset=40&limit=20"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_next_link_pages() {
+        let page = |body: &str| response("200 OK", &["content-type: text/html"], body);
+        let (url, server) = serve(vec![
+            page(r#"<b>990</b><a class="next" href="?page=2">Next</a>"#),
+            page(r#"<b>1050</b><a class="next" href="?page=3">Next</a>"#),
+            page("<p>No more offers</p>"),
+        ])
+        .await;
+        let fetched = price_fetcher(url).fetch().await.unwrap();
+        server.await.unwrap();
+
+        assert_eq!(
+            prices(fetched),
+            vec![
+                FoundItemContent::Str("990".to_string()),
+                FoundItemContent::Str("1050".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_next_link_repeats() {
+        let page = |body: &str| response("200 OK", &["content-type: text/html"], body);
+        let (url, server) = serve(vec![
+            page(r#"<b>990</b><a class="next" href="?page=2">Next</a>"#),
+            page(r#"<b>1050</b><a class="next" href="?page=2">Next</a>"#),
+            // what a second request for the second page would get
+            page("<b>2000</b>"),
+        ])
+        .await;
+
+        let fetched = price_fetcher(url).fetch().await.unwrap();
+        server.abort();
+
+        assert_eq!(
+            prices(fetched),
+            vec![
+                FoundItemContent::Str("990".to_string()),
+                FoundItemContent::Str("1050".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_page() {
+        let page = |headers: &[&str], body: &str| {
+            let headers = [&["content-type: text/html"], headers].concat();
+            response("200 OK", &headers, body)
+        };
+        let (url, server) = serve(vec![
+            page(
+                &["etag: \"1\""],
+                r#"<b>990</b><a class="next" href="?page=2">Next</a>"#,
+            ),
+            page(&["etag: \"2\""], "<b>1050</b>"),
+            page(
+                &["etag: \"1b\""],
+                r#"<b>950</b><a class="next" href="?page=2">Next</a>"#,
+            ),
+            response("304 Not Modified", &[], ""),
+        ])
+        .await;
+        let fetcher = price_fetcher(url);
+        fetcher.fetch().await.unwrap();
+        let fetched = fetcher.fetch().await.unwrap();
+        server.await.unwrap();
+
+        assert_eq!(
+            prices(fetched),
+            vec![
+                FoundItemContent::Str("950".to_string()),
+                FoundItemContent::Str("1050".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_single_page_without_items() {
+        let (url, server) = serve(vec![response(
+            "200 OK",
+            &["content-type: text/html"],
+            "<p>No offers</p>",
+        )])
+        .await;
+        let fetcher = SimpleFetcher {
+            config: FetcherConfig {
+                pagination: None,
+                ..price_fetcher(url).config
+            },
+        };
+        let fetched = fetcher.fetch().await.unwrap();
+        server.await.unwrap();
+
+        // one for the price, as for any aim page
+        assert_eq!(fetched, vec![None]);
+    }
+}
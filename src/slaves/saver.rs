@@ -8,11 +8,16 @@ use tokio::io::AsyncWriteExt;
 
 use super::{
     collector::PgCollector,
-    fetchers::FoundItem,
+    commands::CommandHandler,
+    config_parser::parse_global_config,
+    fetchers::{AimInfo, AimResult},
+    messages::Messages,
     serializer::SerType::{self, *},
 };
 use anyhow::Result;
 use async_recursion::async_recursion;
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Arc};
 
 #[derive(Clone)]
 pub enum SaverType {
@@ -27,26 +32,190 @@ use SaverType::*;
 
 #[derive(Clone)]
 enum SaverBackend {
-    Notifier(TgNotifier),
+    /// Messages of every recipient, they have their own markup and previous values
+    Notifier(TgNotifier, Vec<Messages>),
     Collector(PgCollector),
     Nothing,
 }
 
+/// Sends the results matching all of the given conditions to `sinks`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Route {
+    /// Aims having any of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Aims with any of these ids
+    #[serde(default)]
+    pub aims: Vec<String>,
+    /// Only these items of the aim are sent
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// `stdout`, `file`, `telegram` or `postgres`
+    pub sinks: Vec<String>,
+}
+
+impl Route {
+    /// Part of the result the route takes, `None` when it doesn't match.
+    fn select(&self, result: &AimResult) -> Option<AimResult> {
+        if !self.tags.is_empty() && !self.tags.iter().any(|tag| result.aim.tags.contains(tag)) {
+            return None;
+        }
+        if !self.aims.is_empty() && !self.aims.contains(&result.aim.id) {
+            return None;
+        }
+        let items: Vec<_> = result
+            .items
+            .iter()
+            .filter(|item| self.items.is_empty() || self.items.contains(&item.fetch_item.name))
+            .cloned()
+            .collect();
+        if items.is_empty() && !self.items.is_empty() {
+            return None;
+        }
+        Some(AimResult {
+            aim: result.aim.clone(),
+            items,
+        })
+    }
+}
+
+/// Routing of results between the sinks of a `Multiple` saver, read from `config/saver.yaml`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct SaverConfig {
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Sinks for results no route matches, all of them when not set
+    #[serde(default)]
+    pub unrouted: Option<Vec<String>>,
+    /// Message templates by sink name, used by the plain text `telegram` and `file` sinks
+    #[serde(default)]
+    pub messages: BTreeMap<String, String>,
+}
+
 #[derive(Clone)]
 pub struct Saver {
     stype: SaverType,
     sertype: SerType,
     backend: SaverBackend,
+    config: SaverConfig,
+    messages: Messages,
 }
 
 impl Saver {
     pub async fn new(stype: SaverType, sertype: SerType) -> Self {
         let backend = Self::setup(&stype).await;
+        let config = match stype {
+            Multiple(_) => parse_global_config("config/saver.yaml").unwrap_or_default(),
+            _ => SaverConfig::default(),
+        };
         Saver {
             stype,
             sertype,
             backend,
+            config,
+            messages: Messages::default(),
         }
+        .configured()
+    }
+
+    #[allow(dead_code)]
+    pub fn with_config(self, config: SaverConfig) -> Self {
+        Saver { config, ..self }.configured()
+    }
+
+    /// Message template for the results without one of their own.
+    pub fn with_message(self, template: String) -> Self {
+        let backend = match self.backend {
+            SaverBackend::Notifier(notifier, messages) => SaverBackend::Notifier(
+                notifier,
+                messages
+                    .into_iter()
+                    .map(|messages| messages.with_template(template.clone()))
+                    .collect(),
+            ),
+            backend => backend,
+        };
+        Saver {
+            messages: self.messages.clone().with_template(template),
+            backend,
+            ..self
+        }
+    }
+
+    /// Hands the message templates to the sinks and reports routes to sinks this saver doesn't
+    /// have.
+    fn configured(mut self) -> Self {
+        if let Multiple(sinks) = &mut self.stype {
+            for sink in sinks.iter_mut() {
+                if let Some(template) = self.config.messages.get(sink.name()) {
+                    *sink = sink.clone().with_message(template.clone());
+                }
+            }
+        }
+        if let Multiple(sinks) = &self.stype {
+            let routed = self
+                .config
+                .routes
+                .iter()
+                .flat_map(|route| &route.sinks)
+                .chain(self.config.unrouted.iter().flatten());
+            for name in routed {
+                if !sinks.iter().any(|sink| sink.name() == name) {
+                    eprintln!("Results are routed to {}, but there is no such sink", name);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.stype {
+            Stdout => "stdout",
+            File(_) => "file",
+            Multiple(_) => "multiple",
+            Telegram => "telegram",
+            Postgres => "postgres",
+        }
+    }
+
+    /// Results for every sink of `sinks` according to the routes, all of them without routes.
+    fn route(&self, sinks: &[Saver], data: &[AimResult]) -> Vec<Vec<AimResult>> {
+        if self.config.routes.is_empty() {
+            return vec![data.to_vec(); sinks.len()];
+        }
+        let all_sinks: Vec<String> = sinks.iter().map(|sink| sink.name().to_string()).collect();
+        let unrouted = self.config.unrouted.as_ref().unwrap_or(&all_sinks);
+        let mut routed: Vec<Vec<AimResult>> = vec![vec![]; sinks.len()];
+        for result in data {
+            let mut matched: Vec<_> = self
+                .config
+                .routes
+                .iter()
+                .filter_map(|route| Some((&route.sinks, route.select(result)?)))
+                .collect();
+            if matched.is_empty() {
+                matched.push((unrouted, result.clone()));
+            }
+            for (names, part) in matched {
+                for (sink, sink_data) in sinks.iter().zip(&mut routed) {
+                    if !names.iter().any(|name| name == sink.name()) {
+                        continue;
+                    }
+                    // several routes can take the same result, the sink gets it once
+                    match sink_data.last_mut() {
+                        Some(last) if last.aim == part.aim => {
+                            for item in &part.items {
+                                if !last.items.contains(item) {
+                                    last.items.push(item.clone());
+                                }
+                            }
+                        }
+                        _ => sink_data.push(part.clone()),
+                    }
+                }
+            }
+        }
+        routed
     }
 
     async fn setup(stype: &SaverType) -> SaverBackend {
@@ -56,7 +225,14 @@ impl Saver {
                     eprintln!("{}", err);
                     SaverBackend::Nothing
                 },
-                SaverBackend::Notifier,
+                |notifier| {
+                    let messages = notifier
+                        .recipients()
+                        .iter()
+                        .map(|chat| Messages::default().with_escape(chat.format.escape()))
+                        .collect();
+                    SaverBackend::Notifier(notifier, messages)
+                },
             ),
             Postgres => PgCollector::new().await.map_or_else(
                 |err| {
@@ -82,8 +258,13 @@ impl Saver {
     }
 
     #[async_recursion]
-    pub async fn push(&self, data: Vec<Vec<FoundItem>>) -> Result<()> {
-        let mut ser_data = serialize_all(data.to_vec(), self.sertype);
+    pub async fn push(&self, data: Vec<AimResult>) -> Result<()> {
+        let mut ser_data = match (&self.stype, self.sertype) {
+            (File(_), Plain) => self.messages.render_all(&data),
+            // every recipient gets its own text
+            (Telegram, _) => String::new(),
+            _ => serialize_all(data.to_vec(), self.sertype),
+        };
         match self.stype.clone() {
             Stdout => println!("{}", ser_data),
             File(path) => {
@@ -94,19 +275,36 @@ impl Saver {
                 file.sync_all().await?;
             }
             Multiple(sinks) => {
-                let handlers = sinks.iter().cloned().map(|sink| {
-                    let data = data.clone();
-                    tokio::spawn(async move { sink.push(data).await })
-                });
+                let routed = self.route(&sinks, &data);
+                let routes_set = !self.config.routes.is_empty();
+                let handlers = sinks
+                    .into_iter()
+                    .zip(routed)
+                    .filter(|(_, data)| !routes_set || !data.is_empty())
+                    .map(|(sink, data)| tokio::spawn(async move { sink.push(data).await }));
                 for handler in handlers {
-                    handler.await?;
+                    handler.await??;
                 }
             }
             Telegram => {
-                if let SaverBackend::Notifier(notifier) = &self.backend {
-                    let res = notifier.send(Signal::Msg(ser_data)).await;
-                    if res.is_err() {
-                        eprintln!("{:?}", res)
+                if let SaverBackend::Notifier(notifier, messages) = &self.backend {
+                    for (chat, messages) in notifier.recipients().iter().zip(messages) {
+                        let data: Vec<_> = data
+                            .iter()
+                            .filter(|result| chat.subscribed(&result.aim))
+                            .cloned()
+                            .collect();
+                        if data.is_empty() {
+                            continue;
+                        }
+                        let text = match self.sertype {
+                            Plain => messages.render_all(&data),
+                            _ => (chat.format.escape())(&serialize_all(data, self.sertype)),
+                        };
+                        let res = notifier.send_to(&chat.chat_id, Signal::Msg(text)).await;
+                        if res.is_err() {
+                            eprintln!("{:?}", res)
+                        }
                     }
                 } else {
                     eprintln!("Telegram notifier wasn't initialized. Can not send message")
@@ -114,9 +312,14 @@ impl Saver {
             }
             Postgres => {
                 if let SaverBackend::Collector(collector) = &self.backend {
-                    let res = collector.store(ser_data).await;
-                    if res.is_err() {
-                        eprintln!("{:?}", res)
+                    // a row per aim, so the history of one aim can be queried
+                    for result in data {
+                        let aim_id = result.aim.id.clone();
+                        let ser_data = serialize_all(vec![result], self.sertype);
+                        let res = collector.store(&aim_id, ser_data).await;
+                        if res.is_err() {
+                            eprintln!("{:?}", res)
+                        }
                     }
                 } else {
                     eprintln!("Postgres collector wasn't initialized. Can not store data")
@@ -125,6 +328,44 @@ impl Saver {
         }
         Ok(())
     }
+
+    /// Lets the sinks that talk to a person take commands.
+    #[async_recursion]
+    pub async fn serve_commands(&self, handler: Arc<dyn CommandHandler>) -> Result<()> {
+        match &self.stype {
+            Multiple(sinks) => {
+                for sink in sinks {
+                    sink.serve_commands(handler.clone()).await?;
+                }
+            }
+            Telegram => {
+                if let SaverBackend::Notifier(notifier, _) = &self.backend {
+                    notifier.serve(handler).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Passes a warning about the aims themselves to the sinks that notify a person.
+    #[async_recursion]
+    pub async fn warn(&self, aim: &AimInfo, msg: String) -> Result<()> {
+        match &self.stype {
+            Multiple(sinks) => {
+                for sink in sinks {
+                    sink.warn(aim, msg.clone()).await?;
+                }
+            }
+            Telegram => {
+                if let SaverBackend::Notifier(notifier, _) = &self.backend {
+                    notifier.send(aim, Signal::Warn(msg.clone())).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -132,30 +373,34 @@ mod tests {
     use std::fs;
 
     use crate::slaves::{
-        fetchers::{FetchItem, FetchItemType::*, FoundItem, FoundItemContent::*},
+        fetchers::{
+            AimInfo, AimResult, FetchItem, FetchItemType::*, FoundItem, FoundItemContent::*,
+        },
         serializer::SerType,
     };
 
-    use super::{Saver, SaverType::*};
+    use super::{Saver, SaverConfig, SaverType::*};
 
     use tokio::fs::File;
     use tokio::io::AsyncReadExt;
 
-    fn create_test_data() -> Vec<Vec<FoundItem>> {
+    fn create_test_data() -> Vec<AimResult> {
         let translations = FetchItem {
             name: "translations".to_string(),
-            path: "#Content > div:nth-child(5)".to_string(),
+            path: vec!["#Content > div:nth-child(5)".to_string()],
             primary: false,
             item_type: Class,
             related: vec![],
+            ..Default::default()
         };
 
         let item1 = FetchItem {
             name: "item1".to_string(),
-            path: "#Content > div:nth-child(5) > strong".to_string(),
+            path: vec!["#Content > div:nth-child(5) > strong".to_string()],
             primary: true,
             item_type: Text,
             related: vec![translations.clone()],
+            ..Default::default()
         };
 
         let correct = vec![FoundItem {
@@ -165,11 +410,17 @@ mod tests {
                 fetch_item: translations,
                 content: Arr(vec!["boxed".to_string()]),
                 related: vec![],
+                selector: 0,
             })],
+            selector: 0,
         }];
-        let mut correct = vec![correct];
-        correct.sort();
-        correct
+        vec![AimResult {
+            aim: AimInfo {
+                id: "lipsum".to_string(),
+                ..Default::default()
+            },
+            items: correct,
+        }]
     }
 
     #[tokio::test]
@@ -188,8 +439,75 @@ mod tests {
             .unwrap();
         fs::remove_file(path).unwrap();
 
-        let correct = r#"[[{"name":"item1","content":"Translations:","related":[{"name":"translations","content":["boxed"],"related":[]}]}]]"#.to_string() + "\n";
+        let correct = r#"[{"aim":{"id":"lipsum"},"items":[{"name":"item1","content":"Translations:","related":[{"name":"translations","content":["boxed"],"related":[]}]}]}]"#.to_string() + "\n";
 
         assert_eq!(String::from_utf8(content).unwrap(), correct);
     }
+
+    fn aim_result(id: &str, tags: &[&str], items: &[&str]) -> AimResult {
+        AimResult {
+            aim: AimInfo {
+                id: id.to_string(),
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                ..Default::default()
+            },
+            items: items
+                .iter()
+                .map(|name| FoundItem {
+                    fetch_item: FetchItem {
+                        name: name.to_string(),
+                        ..Default::default()
+                    },
+                    content: Str("1".to_string()),
+                    related: vec![],
+                    selector: 0,
+                })
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes() {
+        let config: SaverConfig = serde_yaml::from_str(
+            r#"
+routes:
+  - tags: [prices]
+    sinks: [telegram]
+  - tags: [debug]
+    sinks: [file]
+  - aims: [kettle]
+    items: [title]
+    sinks: [file]
+unrouted: [stdout]
+"#,
+        )
+        .unwrap();
+        let sinks = vec![
+            Saver::new(Stdout, SerType::Json).await,
+            Saver::new(File("test/routes.out".to_string()), SerType::Json).await,
+            Saver::new(Telegram, SerType::Plain).await,
+        ];
+        let saver = Saver::new(Multiple(sinks.clone()), SerType::Json)
+            .await
+            .with_config(config);
+        let data = vec![
+            aim_result("kettle", &["prices"], &["price", "title"]),
+            aim_result("probe", &["debug"], &["status"]),
+            aim_result("other", &[], &["price"]),
+        ];
+
+        let routed = saver.route(&sinks, &data);
+
+        assert_eq!(
+            routed,
+            vec![
+                vec![data[2].clone()],
+                vec![
+                    aim_result("kettle", &["prices"], &["title"]),
+                    data[1].clone()
+                ],
+                vec![data[0].clone()],
+            ]
+        );
+    }
 }
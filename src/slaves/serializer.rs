@@ -1,6 +1,6 @@
 use crate::slaves::fetchers::FoundItemContent;
 
-use super::fetchers::{FoundItem, FoundItemContent::*};
+use super::fetchers::{AimResult, FoundItem, FoundItemContent::*};
 
 #[derive(Copy, Clone, Debug)]
 pub enum SerType {
@@ -10,17 +10,24 @@ pub enum SerType {
 
 use SerType::*;
 
-pub fn serialize_all(fetched_configs: Vec<Vec<FoundItem>>, sertype: SerType) -> String {
+pub fn serialize_all(fetched_configs: Vec<AimResult>, sertype: SerType) -> String {
     match sertype {
-        Plain => {
-            let mut result = vec![];
-            for config in fetched_configs {
-                for item in config {
-                    result.push(serialize_plain(item))
+        Plain => fetched_configs
+            .into_iter()
+            .map(|config| {
+                let items = config
+                    .items
+                    .into_iter()
+                    .map(serialize_plain)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                match config.aim.label() {
+                    "" => items,
+                    label => format!("[{}] {}", label, items),
                 }
-            }
-            result.join(" ")
-        }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
         Json => serde_json::to_string(&fetched_configs).unwrap(),
     }
 }
@@ -49,24 +56,28 @@ fn serialize_plain(item: FoundItem) -> String {
 
 #[cfg(test)]
 mod tests {
-    use crate::slaves::fetchers::{FetchItem, FetchItemType::*, FoundItem, FoundItemContent::*};
+    use crate::slaves::fetchers::{
+        AimInfo, AimResult, FetchItem, FetchItemType::*, FoundItem, FoundItemContent::*,
+    };
     use crate::slaves::serializer::{serialize_all, SerType::*};
 
-    fn create_test_data() -> Vec<Vec<FoundItem>> {
+    fn create_test_data() -> Vec<AimResult> {
         let translations = FetchItem {
             name: "translations".to_string(),
-            path: "#Content > div:nth-child(5)".to_string(),
+            path: vec!["#Content > div:nth-child(5)".to_string()],
             primary: false,
             item_type: Class,
             related: vec![],
+            ..Default::default()
         };
 
         let item1 = FetchItem {
             name: "item1".to_string(),
-            path: "#Content > div:nth-child(5) > strong".to_string(),
+            path: vec!["#Content > div:nth-child(5) > strong".to_string()],
             primary: true,
             item_type: Text,
             related: vec![translations.clone()],
+            ..Default::default()
         };
 
         let correct = vec![FoundItem {
@@ -76,11 +87,18 @@ mod tests {
                 fetch_item: translations,
                 content: Arr(vec!["boxed".to_string()]),
                 related: vec![],
+                selector: 0,
             })],
+            selector: 0,
         }];
-        let mut correct = vec![correct];
-        correct.sort();
-        correct
+        vec![AimResult {
+            aim: AimInfo {
+                id: "lipsum".to_string(),
+                tags: vec!["debug".to_string()],
+                ..Default::default()
+            },
+            items: correct,
+        }]
     }
 
     #[test]
@@ -89,7 +107,7 @@ mod tests {
 
         assert_eq!(
             serialize_all(data, Plain),
-            "item1=Translations:: translations=boxed".to_string()
+            "[lipsum] item1=Translations:: translations=boxed".to_string()
         )
     }
 
@@ -99,7 +117,7 @@ mod tests {
 
         assert_eq!(
             serialize_all(data, Json),
-            r#"[[{"name":"item1","content":"Translations:","related":[{"name":"translations","content":["boxed"],"related":[]}]}]]"#
+            r#"[{"aim":{"id":"lipsum","tags":["debug"]},"items":[{"name":"item1","content":"Translations:","related":[{"name":"translations","content":["boxed"],"related":[]}]}]}]"#
         )
     }
 }
@@ -0,0 +1,223 @@
+use anyhow::{anyhow, Result};
+use scraper::{ElementRef, Html, Selector};
+use serde_json::Value;
+
+use super::clients::json::JsonFetcher;
+
+/// Paths look like `Product/offers/price`: a schema.org type, then the property path.
+fn split_path(path: &str) -> Result<(&str, Vec<&str>)> {
+    let mut parts = path
+        .split('/')
+        .map(str::trim)
+        .filter(|part| !part.is_empty());
+    let schema_type = parts
+        .next()
+        .ok_or_else(|| anyhow!("Empty structured data path"))?;
+    Ok((schema_type, parts.collect()))
+}
+
+/// `Product` matches `Product`, `http://schema.org/Product` and `https://schema.org/Product`.
+fn is_type(declared: &str, schema_type: &str) -> bool {
+    declared == schema_type || declared.ends_with(&format!("/{}", schema_type))
+}
+
+fn has_type(value: &Value, schema_type: &str) -> bool {
+    match value.get("@type") {
+        Some(Value::String(declared)) => is_type(declared, schema_type),
+        Some(Value::Array(declared)) => declared
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|declared| is_type(declared, schema_type)),
+        _ => false,
+    }
+}
+
+/// Objects of the type anywhere in the document, `@graph` and nested entities included.
+fn typed_objects<'v>(value: &'v Value, schema_type: &str, found: &mut Vec<&'v Value>) {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .for_each(|item| typed_objects(item, schema_type, found)),
+        Value::Object(map) => {
+            if has_type(value, schema_type) {
+                found.push(value);
+            }
+            map.values()
+                .for_each(|item| typed_objects(item, schema_type, found));
+        }
+        _ => {}
+    }
+}
+
+/// Property of an object, numbers index arrays and other arrays give their first entry having it.
+fn property<'v>(value: &'v Value, name: &str) -> Option<&'v Value> {
+    match value {
+        Value::Array(items) => match name.parse::<usize>() {
+            Ok(index) => items.get(index),
+            Err(_) => items.iter().find_map(|item| property(item, name)),
+        },
+        Value::Object(map) => map.get(name),
+        _ => None,
+    }
+}
+
+/// Value from the `<script type="application/ld+json">` blocks of the page.
+pub fn json_ld(tree: &Html, path: &str) -> Result<String> {
+    let (schema_type, properties) = split_path(path)?;
+    let selector = Selector::parse(r#"script[type="application/ld+json"]"#).unwrap();
+    // broken blocks are common and shouldn't hide the valid ones
+    let documents: Vec<Value> = tree
+        .select(&selector)
+        .filter_map(|script| serde_json::from_str(&script.text().collect::<String>()).ok())
+        .collect();
+    let mut objects = vec![];
+    documents
+        .iter()
+        .for_each(|document| typed_objects(document, schema_type, &mut objects));
+    objects
+        .into_iter()
+        .find_map(|object| {
+            properties
+                .iter()
+                .try_fold(object, |value, name| property(value, name))
+        })
+        .map(JsonFetcher::to_text)
+        .ok_or_else(|| anyhow!("Select failed"))
+}
+
+fn has_token(element: &ElementRef, attr: &str, token: &str) -> bool {
+    element
+        .value()
+        .attr(attr)
+        .is_some_and(|value| value.split_whitespace().any(|part| part == token))
+}
+
+/// Whether `scope` is the nearest `itemscope` around the element.
+fn owned_by(element: &ElementRef, scope: &ElementRef) -> bool {
+    element
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .find(|ancestor| ancestor.value().attr("itemscope").is_some())
+        .is_some_and(|owner| owner.id() == scope.id())
+}
+
+fn item_property<'a>(scope: ElementRef<'a>, name: &str) -> Option<ElementRef<'a>> {
+    scope
+        .descendants()
+        .skip(1)
+        .filter_map(ElementRef::wrap)
+        .find(|element| has_token(element, "itemprop", name) && owned_by(element, &scope))
+}
+
+/// Value of an `itemprop` element the way the microdata spec reads it.
+fn microdata_value(element: ElementRef) -> String {
+    let value = element.value();
+    let attr = match value.name() {
+        "meta" => "content",
+        "a" | "area" | "link" => "href",
+        "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video" => "src",
+        "object" => "data",
+        "data" | "meter" => "value",
+        "time" => "datetime",
+        _ => "content",
+    };
+    value
+        .attr(attr)
+        .map(str::to_string)
+        .unwrap_or_else(|| element.text().collect::<String>().trim().to_string())
+}
+
+/// Value from `itemscope`/`itemprop` markup.
+pub fn microdata(tree: &Html, path: &str) -> Result<String> {
+    let (schema_type, properties) = split_path(path)?;
+    let selector = Selector::parse("[itemscope][itemtype]").unwrap();
+    tree.select(&selector)
+        .filter(|scope| {
+            scope.value().attr("itemtype").is_some_and(|declared| {
+                declared
+                    .split_whitespace()
+                    .any(|declared| is_type(declared, schema_type))
+            })
+        })
+        .find_map(|scope| {
+            properties
+                .iter()
+                .try_fold(scope, |scope, name| item_property(scope, name))
+        })
+        .map(microdata_value)
+        .ok_or_else(|| anyhow!("Select failed"))
+}
+
+/// `content` of `<meta property="og:price:amount">` or `<meta name="description">`.
+pub fn meta(tree: &Html, name: &str) -> Result<String> {
+    let selector = Selector::parse("meta[content]").unwrap();
+    tree.select(&selector)
+        .map(|element| element.value())
+        .find(|element| {
+            element.attr("property") == Some(name) || element.attr("name") == Some(name)
+        })
+        .and_then(|element| element.attr("content"))
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Select failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use scraper::Html;
+
+    use super::{json_ld, meta, microdata};
+
+    const PAGE: &str = r#"<html><head>
+        <meta property="og:title" content="AirPods Pro">
+        <meta name="description" content="Wireless earbuds">
+        <script type="application/ld+json">{ broken </script>
+        <script type="application/ld+json">
+            {"@context": "https://schema.org", "@graph": [
+                {"@type": "BreadcrumbList", "name": "Audio"},
+                {"@type": ["Product"], "name": "AirPods Pro",
+                 "offers": [{"@type": "Offer", "price": 19990, "availability": "https://schema.org/InStock"}]}
+            ]}
+        </script>
+        </head><body>
+        <div itemscope itemtype="http://schema.org/Product">
+            <h1 itemprop="name">AirPods Pro</h1>
+            <div itemprop="brand" itemscope itemtype="http://schema.org/Brand"><span itemprop="name">Apple</span></div>
+            <div itemprop="offers" itemscope itemtype="http://schema.org/Offer">
+                <meta itemprop="priceCurrency" content="RUB"><span itemprop="price">19 990</span>
+            </div>
+        </div>
+        </body></html>"#;
+
+    #[test]
+    fn test_json_ld() {
+        let tree = Html::parse_document(PAGE);
+        assert_eq!(json_ld(&tree, "Product/offers/price").unwrap(), "19990");
+        assert_eq!(
+            json_ld(&tree, "Product/offers/0/availability").unwrap(),
+            "https://schema.org/InStock"
+        );
+        assert_eq!(json_ld(&tree, "Offer/price").unwrap(), "19990");
+        assert!(json_ld(&tree, "Product/sku").is_err());
+    }
+
+    #[test]
+    fn test_microdata() {
+        let tree = Html::parse_document(PAGE);
+        assert_eq!(microdata(&tree, "Product/name").unwrap(), "AirPods Pro");
+        assert_eq!(microdata(&tree, "Product/brand/name").unwrap(), "Apple");
+        assert_eq!(
+            microdata(&tree, "Product/offers/priceCurrency").unwrap(),
+            "RUB"
+        );
+        assert_eq!(microdata(&tree, "Offer/price").unwrap(), "19 990");
+        assert!(microdata(&tree, "Product/price").is_err());
+    }
+
+    #[test]
+    fn test_meta() {
+        let tree = Html::parse_document(PAGE);
+        assert_eq!(meta(&tree, "og:title").unwrap(), "AirPods Pro");
+        assert_eq!(meta(&tree, "description").unwrap(), "Wireless earbuds");
+        assert!(meta(&tree, "og:image").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use super::{config_parser::map_strings, fetchers::FetcherConfig};
+
+type Params = BTreeMap<String, String>;
+
+/// Aim file describing many aims at once: `{{variable}}` placeholders anywhere in `template`
+/// are filled from each parameter set. The rest of the placeholders in `message` are left to
+/// the message template.
+#[derive(Deserialize, Debug)]
+pub struct AimTemplate {
+    template: Value,
+    #[serde(default)]
+    params: Vec<BTreeMap<String, Value>>,
+    /// CSV file with variable names in the header row, relative to the template file
+    #[serde(default)]
+    params_csv: Option<String>,
+}
+
+impl AimTemplate {
+    pub fn is_template(value: &Value) -> bool {
+        value.get("template").is_some()
+    }
+
+    fn scalar(value: &Value) -> Result<String> {
+        match value {
+            Value::String(text) => Ok(text.clone()),
+            Value::Number(number) => Ok(number.to_string()),
+            Value::Bool(flag) => Ok(flag.to_string()),
+            Value::Null => Ok(String::new()),
+            other => Err(anyhow!("Template parameter {:?} is not a scalar", other)),
+        }
+    }
+
+    fn param_sets(&self, base_dir: &Path) -> Result<Vec<Params>> {
+        let mut sets = self
+            .params
+            .iter()
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), Self::scalar(value)?)))
+                    .collect::<Result<Params>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(csv_file) = &self.params_csv {
+            let path = base_dir.join(csv_file);
+            let mut reader =
+                csv::Reader::from_path(&path).with_context(|| format!("Can't read {:?}", path))?;
+            let headers = reader.headers()?.clone();
+            for record in reader.records() {
+                let record = record?;
+                sets.push(
+                    headers
+                        .iter()
+                        .map(|name| name.trim().to_string())
+                        .zip(record.iter().map(|value| value.trim().to_string()))
+                        .collect(),
+                );
+            }
+        }
+        Ok(sets)
+    }
+
+    /// `message` is a Handlebars template of its own, only the parameters are filled in it.
+    fn fill_template(&self, params: &Params) -> Result<Value> {
+        let mut template = self.template.clone();
+        let message = match &mut template {
+            Value::Mapping(map) => map.remove(&Value::from("message")),
+            _ => None,
+        };
+        let mut value = map_strings(&template, &mut |text| fill(text, params, true))?;
+        if let (Some(message), Value::Mapping(map)) = (message, &mut value) {
+            let message = map_strings(&message, &mut |text| fill(text, params, false))?;
+            map.insert(Value::from("message"), message);
+        }
+        Ok(value)
+    }
+
+    /// One config per parameter set, `base_dir` is where `params_csv` is looked up.
+    pub fn expand(&self, base_dir: &Path) -> Result<Vec<FetcherConfig>> {
+        self.param_sets(base_dir)?
+            .iter()
+            .map(|params| {
+                let value = self.fill_template(params)?;
+                serde_yaml::from_value(value)
+                    .with_context(|| format!("Template expanded with {:?} is not an aim", params))
+            })
+            .collect()
+    }
+}
+
+/// Placeholders without a parameter are an error when `strict`, otherwise they're left as is.
+fn fill(text: &str, params: &Params, strict: bool) -> Result<String> {
+    let mut filled = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| anyhow!("Unclosed {{{{ in {}", text))?;
+        let name = rest[start + 2..start + end].trim();
+        let value = match params.get(name) {
+            Some(value) => value,
+            None if !strict => &rest[start..start + end + 2],
+            None => return Err(anyhow!("Template variable {} has no value", name)),
+        };
+        filled.push_str(&rest[..start]);
+        filled.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::AimTemplate;
+
+    #[test]
+    fn test_expand() {
+        let content = fs::read_to_string("test/templates/products.yaml").unwrap();
+        let template: AimTemplate = serde_yaml::from_str(&content).unwrap();
+        let configs = template.expand(Path::new("test/templates")).unwrap();
+
+        let expanded: Vec<_> = configs
+            .iter()
+            .map(|config| (config.url.as_str(), config.items[0].name.as_str()))
+            .collect();
+        assert_eq!(
+            expanded,
+            vec![
+                ("https://shop.example/product/1001", "price_kettle"),
+                ("https://shop.example/product/1002", "price_toaster"),
+                ("https://shop.example/product/2001", "price_blender"),
+            ]
+        );
+        assert_eq!(configs[0].items[0].path, configs[2].items[0].path);
+    }
+
+    #[test]
+    fn test_missing_variable() {
+        let template: AimTemplate = serde_yaml::from_str(
+            "template:\n  url: \"https://shop.example/{{sku}}\"\n  items: []\nparams:\n  - name: kettle\n",
+        )
+        .unwrap();
+        let err = template.expand(Path::new(".")).unwrap_err();
+        assert!(format!("{:?}", err).contains("sku"));
+    }
+
+    #[test]
+    fn test_message() {
+        let template: AimTemplate = serde_yaml::from_str(
+            r#"
+template:
+  url: "https://shop.example/{{sku}}"
+  message: "{{label}} ({{name}}): {{item.price.value}}{{#if changed}} {{item.price.diff}}{{/if}}"
+  items: []
+params:
+  - sku: 1001
+    name: kettle
+"#,
+        )
+        .unwrap();
+        let configs = template.expand(Path::new(".")).unwrap();
+        assert_eq!(configs[0].url, "https://shop.example/1001");
+        assert_eq!(
+            configs[0].message.as_deref(),
+            Some("{{label}} (kettle): {{item.price.value}}{{#if changed}} {{item.price.diff}}{{/if}}")
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::cell::OnceCell;
+
+use anyhow::{anyhow, Result};
+use ego_tree::NodeRef;
+use scraper::{Html, Node};
+use sxd_document::{
+    dom::{Document, Element},
+    Package,
+};
+use sxd_xpath::{evaluate_xpath, nodeset, Value};
+
+use super::fetchers::{FetchItemType, FoundItemContent};
+
+/// Copies the tree html5ever built into an XML document, so XPath works on broken markup too.
+/// Elements lose their namespace, `//div` matches html `div`s.
+fn to_document(tree: &Html) -> Package {
+    let package = Package::new();
+    let document = package.as_document();
+    for child in tree.tree.root().children() {
+        if let Some(element) = copy_node(&document, child) {
+            document.root().append_child(element);
+        }
+    }
+    package
+}
+
+fn copy_node<'d>(document: &Document<'d>, node: NodeRef<Node>) -> Option<Element<'d>> {
+    let html_element = node.value().as_element()?;
+    let element = document.create_element(html_element.name());
+    for (name, value) in html_element.attrs() {
+        element.set_attribute_value(name, value);
+    }
+    for child in node.children() {
+        match child.value() {
+            Node::Text(text) => element.append_child(document.create_text(text)),
+            Node::Element(_) => {
+                if let Some(child_element) = copy_node(document, child) {
+                    element.append_child(child_element)
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(element)
+}
+
+fn classes(value: &str) -> FoundItemContent {
+    FoundItemContent::Arr(value.split_whitespace().map(str::to_string).collect())
+}
+
+/// XML copy of a page, made on the first XPath lookup and shared by the items of the page.
+#[derive(Default)]
+pub struct XmlDocument(OnceCell<Package>);
+
+impl XmlDocument {
+    /// First result of the expression. Elements give their text, `Class` items give
+    /// the `class` of a matched element or split a matched attribute value.
+    pub fn find(
+        &self,
+        tree: &Html,
+        path: &str,
+        item_type: &FetchItemType,
+    ) -> Result<FoundItemContent> {
+        let document = self.0.get_or_init(|| to_document(tree)).as_document();
+        evaluate(&document, path, item_type)
+    }
+}
+
+fn evaluate(
+    document: &Document,
+    path: &str,
+    item_type: &FetchItemType,
+) -> Result<FoundItemContent> {
+    let value = evaluate_xpath(document, path)
+        .map_err(|err| anyhow!("XPath {} errored {:?}", path, err))?;
+    let text = match value {
+        Value::Nodeset(nodes) => {
+            let node = nodes
+                .document_order_first()
+                .ok_or_else(|| anyhow!("Select failed"))?;
+            match (item_type, node) {
+                (FetchItemType::Class, nodeset::Node::Element(element)) => {
+                    return Ok(classes(
+                        element.attribute_value("class").unwrap_or_default(),
+                    ))
+                }
+                (_, node) => node.string_value(),
+            }
+        }
+        Value::String(text) => text,
+        Value::Number(number) => number.to_string(),
+        Value::Boolean(flag) => flag.to_string(),
+    };
+    Ok(match item_type {
+        FetchItemType::Class => classes(&text),
+        _ => FoundItemContent::Str(text),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use scraper::Html;
+
+    use crate::slaves::fetchers::{FetchItemType, FoundItemContent};
+
+    use super::XmlDocument;
+
+    #[test]
+    fn test_find() {
+        let tree = Html::parse_document(
+            r#"<div class="card main"><span>Price:</span><b>990</b><a href="/buy">Buy<p>broken</div>"#,
+        );
+        let document = XmlDocument::default();
+        let find = |path, item_type| document.find(&tree, path, item_type);
+        let text = |path| find(path, &FetchItemType::Text).unwrap();
+        assert_eq!(
+            text("//span[contains(text(), 'Price')]/following-sibling::b"),
+            FoundItemContent::Str("990".to_string())
+        );
+        assert_eq!(
+            text("//b/ancestor::div//a/@href"),
+            FoundItemContent::Str("/buy".to_string())
+        );
+        assert_eq!(text("count(//div)"), FoundItemContent::Str("1".to_string()));
+        assert_eq!(
+            find("//b/..", &FetchItemType::Class).unwrap(),
+            FoundItemContent::Arr(vec!["card".to_string(), "main".to_string()])
+        );
+        assert!(find("//table", &FetchItemType::Text).is_err());
+        assert!(find("//[", &FetchItemType::Text).is_err());
+    }
+}